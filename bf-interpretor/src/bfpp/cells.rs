@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+
+use super::emit::{SCRATCH_END, SCRATCH_PRINT, SCRATCH_SET};
+use super::error::Error;
+use super::lexer::{ident_at, matching, skip_ws};
+use super::mapped::{Mapped, Origin};
+
+struct Loop {
+    start: Option<i64>,
+    line: usize,
+    moved: bool,
+    delta: i64,
+    checked: bool,
+}
+
+enum Block {
+    If { flag: i64, copy: i64, has_else: bool },
+    Else { flag: i64 },
+    While { cond: i64 },
+    Times { counter: i64 },
+    Balanced { delta: i64, loops: usize },
+}
+
+struct Resolver {
+    vars: HashMap<String, i64>,
+    scratch: Vec<(i64, bool)>,
+    borrowed: Option<(i64, i64)>,
+    next_cell: i64,
+    pos: Option<i64>,
+    delta: i64,
+    lost_at: usize,
+    loops: Vec<Loop>,
+    blocks: Vec<(Block, usize)>,
+    at: Origin,
+    start: usize,
+    out: Mapped,
+}
+
+impl Resolver {
+    fn op(&mut self, b: u8, line: usize) -> Result<(), Error> {
+        match b {
+            b'>' => {
+                self.pos = self.pos.map(|p| p + 1);
+                self.delta += 1;
+            }
+            b'<' => {
+                self.pos = self.pos.map(|p| p - 1);
+                self.delta -= 1;
+            }
+            b'[' => self.loops.push(Loop {
+                start: self.pos,
+                line,
+                moved: false,
+                delta: self.delta,
+                checked: self.blocks.iter().any(|(b, _)| matches!(b, Block::Balanced { .. })),
+            }),
+            b']' => {
+                if let Some(open) = self.loops.pop() {
+                    if open.checked && open.delta != self.delta {
+                        return Err(cell_error(format!(
+                            "loop opened on line {} inside @balanced moves the pointer by {:+}",
+                            open.line,
+                            self.delta - open.delta
+                        )));
+                    }
+                    if open.start.is_none() || open.start != self.pos {
+                        if open.moved {
+                            return Err(cell_error(format!(
+                                "named cell used inside unbalanced loop opened on line {}",
+                                open.line
+                            )));
+                        }
+                        if self.pos.is_some() {
+                            self.lost_at = open.line;
+                        }
+                        self.pos = None;
+                    }
+                    if let Some(outer) = self.loops.last_mut() {
+                        outer.moved |= open.moved;
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.out.push(b as char, self.at);
+        Ok(())
+    }
+
+    fn emit(&mut self, code: &str, line: usize) -> Result<(), Error> {
+        for b in code.bytes() {
+            self.op(b, line)?;
+        }
+        Ok(())
+    }
+
+    fn goto(&mut self, target: i64) -> Result<(), Error> {
+        let current = self.pos.ok_or_else(|| {
+            cell_error(format!(
+                "pointer position is not statically known (unbalanced loop on line {})",
+                self.lost_at
+            ))
+        })?;
+        let step = if target > current { '>' } else { '<' };
+        for _ in 0..(target - current).abs() {
+            self.out.push(step, self.at);
+        }
+        self.pos = Some(target);
+        self.delta += target - current;
+        if let Some(open) = self.loops.last_mut() {
+            open.moved = true;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<i64, Error> {
+        self.vars
+            .get(name)
+            .copied()
+            .ok_or_else(|| cell_error(format!("unknown cell '{}'", name)))
+    }
+
+    fn declare(&mut self, name: &str) -> Result<i64, Error> {
+        if self.vars.contains_key(name) {
+            return Err(cell_error(format!("cell '{}' redeclared", name)));
+        }
+        let cell = self.next_cell;
+        self.vars.insert(name.to_string(), cell);
+        self.next_cell += 1;
+        Ok(cell)
+    }
+
+    fn claim(&mut self, count: usize, directive: &str) -> Result<Vec<i64>, Error> {
+        let free: Vec<usize> = (0..self.scratch.len())
+            .filter(|&idx| !self.scratch[idx].1)
+            .take(count)
+            .collect();
+        if free.len() < count {
+            return Err(cell_error(format!(
+                "@{} needs {} free scratch cell(s); declare more with @scratch",
+                directive, count
+            )));
+        }
+        Ok(free
+            .into_iter()
+            .map(|idx| {
+                self.scratch[idx].1 = true;
+                self.scratch[idx].0
+            })
+            .collect())
+    }
+
+    fn release(&mut self, cell: i64) {
+        if let Some(slot) = self.scratch.iter_mut().find(|(c, _)| *c == cell) {
+            slot.1 = false;
+        }
+    }
+
+    fn borrow_scratch(&mut self, directive: &str) -> Result<(), Error> {
+        let Some(home) = self.pos else {
+            return Ok(());
+        };
+        let right = home + 1;
+        let Some(name) = self.vars.iter().find(|(_, &cell)| cell == right).map(|(n, _)| n.clone())
+        else {
+            return Ok(());
+        };
+        if self.scratch.iter().any(|&(cell, used)| cell == right && !used) {
+            return Ok(());
+        }
+        let cell = self.claim(1, directive).map_err(|_| {
+            cell_error(format!(
+                "@{} needs the cell right of the pointer as scratch, but it is '{}'; \
+                 declare a free cell with @scratch",
+                directive, name
+            ))
+        })?[0];
+        self.borrowed = Some((home, cell));
+        Ok(())
+    }
+
+    fn return_scratch(&mut self) {
+        if let Some((_, cell)) = self.borrowed.take() {
+            self.release(cell);
+        }
+    }
+
+    fn copy_into(&mut self, src: i64, dst: i64, tmp: i64, line: usize) -> Result<(), Error> {
+        self.goto(tmp)?;
+        self.emit("[-]", line)?;
+        self.goto(dst)?;
+        self.emit("[-]", line)?;
+        self.goto(src)?;
+        self.emit("[", line)?;
+        self.goto(tmp)?;
+        self.emit("+", line)?;
+        self.goto(dst)?;
+        self.emit("+", line)?;
+        self.goto(src)?;
+        self.emit("-]", line)?;
+        self.goto(tmp)?;
+        self.emit("[", line)?;
+        self.goto(src)?;
+        self.emit("+", line)?;
+        self.goto(tmp)?;
+        self.emit("-]", line)
+    }
+
+    fn open_block(
+        &mut self,
+        directive: &str,
+        cond: i64,
+        has_else: bool,
+        line: usize,
+    ) -> Result<(), Error> {
+        let block = match directive {
+            "if" => {
+                let cells = self.claim(2, directive)?;
+                let (flag, copy) = (cells[0], cells[1]);
+                self.copy_into(cond, copy, flag, line)?;
+                if has_else {
+                    self.goto(flag)?;
+                    self.emit("+", line)?;
+                }
+                self.goto(copy)?;
+                self.emit("[", line)?;
+                Block::If { flag, copy, has_else }
+            }
+            "while" => {
+                self.goto(cond)?;
+                self.emit("[", line)?;
+                Block::While { cond }
+            }
+            _ => {
+                let cells = self.claim(2, directive)?;
+                let (tmp, counter) = (cells[0], cells[1]);
+                self.copy_into(cond, counter, tmp, line)?;
+                self.release(tmp);
+                self.goto(counter)?;
+                self.emit("[", line)?;
+                Block::Times { counter }
+            }
+        };
+        self.blocks.push((block, self.start));
+        Ok(())
+    }
+
+    fn close_block(&mut self, block: Block, line: usize) -> Result<(), Error> {
+        match block {
+            Block::If { flag, copy, has_else } => {
+                self.goto(copy)?;
+                self.emit("[-]", line)?;
+                if has_else {
+                    self.goto(flag)?;
+                    self.emit("-", line)?;
+                }
+                self.goto(copy)?;
+                self.emit("]", line)?;
+                self.release(copy);
+                if !has_else {
+                    self.release(flag);
+                }
+            }
+            Block::Else { flag } => {
+                self.goto(flag)?;
+                self.emit("-]", line)?;
+                self.release(flag);
+            }
+            Block::While { cond } => {
+                self.goto(cond)?;
+                self.emit("]", line)?;
+            }
+            Block::Times { counter } => {
+                self.goto(counter)?;
+                self.emit("-]", line)?;
+                self.release(counter);
+            }
+            Block::Balanced { delta, loops } => {
+                if loops != self.loops.len() {
+                    return Err(cell_error("loop crosses the end of @balanced block".to_string()));
+                }
+                if delta != self.delta {
+                    return Err(cell_error(format!(
+                        "@balanced block moves the pointer by {:+}",
+                        self.delta - delta
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn resolve_cells(source: &Mapped) -> Result<Mapped, Error> {
+    let mut resolver = Resolver {
+        vars: HashMap::new(),
+        scratch: Vec::new(),
+        borrowed: None,
+        next_cell: 0,
+        pos: Some(0),
+        delta: 0,
+        lost_at: 0,
+        loops: Vec::new(),
+        blocks: Vec::new(),
+        at: Origin::default(),
+        start: 0,
+        out: source.derived(),
+    };
+    run(&mut resolver, source).map_err(|err| err.at(source, resolver.start))?;
+    Ok(resolver.out)
+}
+
+fn run(resolver: &mut Resolver, source: &Mapped) -> Result<(), Error> {
+    let text = source.text.as_str();
+    let bytes = text.as_bytes();
+    let line_of = |pos: usize| source.origin(pos).line;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        resolver.at = source.origin(i);
+        resolver.start = i;
+        if b == b'}' && !resolver.blocks.is_empty() {
+            let line = line_of(i);
+            let (block, _) = resolver.blocks.pop().unwrap();
+            let flag = match block {
+                Block::If { flag, has_else: true, .. } => Some(flag),
+                _ => None,
+            };
+            resolver.close_block(block, line)?;
+            i += 1;
+            if let Some(flag) = flag {
+                let at = skip_ws(text, i);
+                let open = skip_ws(text, at + "@else".len());
+                resolver.out.push_range(source, i..at);
+                resolver.goto(flag)?;
+                resolver.emit("[", line)?;
+                resolver.blocks.push((Block::Else { flag }, at));
+                i = open + 1;
+            }
+            continue;
+        }
+        if b != b'@' {
+            let line = if b == b'[' { line_of(i) } else { 0 };
+            match (b, resolver.borrowed) {
+                (b'>', Some((_, cell))) => resolver.goto(cell)?,
+                (b'<', Some((home, _))) => resolver.goto(home)?,
+                _ if b.is_ascii() => resolver.op(b, line)?,
+                _ => {
+                    let ch = text[i..].chars().next().unwrap_or_default();
+                    match ch {
+                        SCRATCH_PRINT => resolver.borrow_scratch("print")?,
+                        SCRATCH_SET => resolver.borrow_scratch("set")?,
+                        SCRATCH_END => resolver.return_scratch(),
+                        _ => resolver.out.push_range(source, i..i + ch.len_utf8()),
+                    }
+                    i += ch.len_utf8();
+                    continue;
+                }
+            }
+            i += 1;
+            continue;
+        }
+        let directive = ident_at(text, i + 1);
+        if !matches!(
+            directive,
+            "var" | "scratch" | "at" | "goto" | "if" | "while" | "times" | "else" | "balanced"
+                | "assert_pos"
+        ) {
+            resolver.op(b, 0)?;
+            i += 1;
+            continue;
+        }
+        let line = line_of(i);
+        if directive == "else" {
+            return Err(cell_error("@else without matching @if".to_string()));
+        }
+        if directive == "balanced" {
+            let open = skip_ws(text, i + 1 + directive.len());
+            if bytes.get(open) != Some(&b'{') {
+                return Err(cell_error("expected '{' after @balanced".to_string()));
+            }
+            if matching(text, open, b'{', b'}').is_none() {
+                return Err(cell_error("missing '}' for @balanced".to_string()));
+            }
+            let block = Block::Balanced {
+                delta: resolver.delta,
+                loops: resolver.loops.len(),
+            };
+            resolver.blocks.push((block, i));
+            i = open + 1;
+            continue;
+        }
+        let name_start = skip_ws(text, i + 1 + directive.len());
+        let name = ident_at(text, name_start);
+        if name.is_empty() || name_start == i + 1 + directive.len() {
+            return Err(cell_error(format!(
+                "expected cell name after @{}",
+                directive
+            )));
+        }
+        i = name_start + name.len();
+        match directive {
+            "var" => {
+                resolver.declare(name)?;
+            }
+            "scratch" => {
+                let cell = resolver.declare(name)?;
+                resolver.scratch.push((cell, false));
+            }
+            "at" | "goto" => {
+                let target = resolver.lookup(name)?;
+                resolver.goto(target)?;
+            }
+            "assert_pos" => {
+                let expected = resolver.lookup(name)?;
+                match resolver.pos {
+                    Some(pos) if pos == expected => {}
+                    Some(pos) => {
+                        return Err(cell_error(format!(
+                            "@assert_pos {} failed: pointer is at cell {}, expected cell {}",
+                            name, pos, expected
+                        )));
+                    }
+                    None => {
+                        return Err(cell_error(format!(
+                            "@assert_pos {} failed: pointer position is not statically known \
+                             (unbalanced loop on line {})",
+                            name, resolver.lost_at
+                        )));
+                    }
+                }
+            }
+            _ => {
+                let cond = resolver.lookup(name)?;
+                let open = skip_ws(text, i);
+                if bytes.get(open) != Some(&b'{') {
+                    return Err(cell_error(format!(
+                        "expected '{{' after @{} {}",
+                        directive, name
+                    )));
+                }
+                let close = matching(text, open, b'{', b'}').ok_or_else(|| {
+                    cell_error(format!("missing '}}' for @{}", directive))
+                })?;
+                let after = skip_ws(text, close + 1);
+                let has_else = directive == "if"
+                    && bytes.get(after) == Some(&b'@')
+                    && ident_at(text, after + 1) == "else";
+                if has_else {
+                    let else_open = skip_ws(text, after + "@else".len());
+                    if bytes.get(else_open) != Some(&b'{') {
+                        resolver.start = after;
+                        return Err(cell_error("expected '{' after @else".to_string()));
+                    }
+                }
+                resolver.open_block(directive, cond, has_else, line)?;
+                i = open + 1;
+            }
+        }
+    }
+    if let Some(&(_, open)) = resolver.blocks.last() {
+        resolver.start = open;
+        return Err(cell_error("missing '}' for block".to_string()));
+    }
+    Ok(())
+}
+
+fn cell_error(message: String) -> Error {
+    Error::CellError { message }
+}
@@ -1,19 +1,61 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use super::cells;
+use super::emit;
 use super::error::Error;
 use super::include;
+use super::macros;
+use super::mapped::Mapped;
+use super::pretty;
 use super::repeat;
 
-pub fn preprocess(input_path: &Path) -> Result<String, Error> {
-    let resolved = include::resolve_includes(input_path)?;
-    let stripped = strip_comments(&resolved);
-    let expanded = repeat::expand_repeats(&stripped)?;
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub include_dirs: Vec<PathBuf>,
+    pub defines: Vec<(String, String)>,
+    pub verbose: bool,
+    pub keep_comments: bool,
+    pub pretty: bool,
+}
+
+pub fn preprocess(input_path: &Path, options: &Options) -> Result<String, Error> {
+    Ok(preprocess_mapped(input_path, options)?.text)
+}
+
+pub fn preprocess_mapped(input_path: &Path, options: &Options) -> Result<Mapped, Error> {
+    let resolved = include::resolve_includes(input_path, options)?;
+    expand(&resolved, options)
+}
+
+pub fn preprocess_source(
+    input_path: &Path,
+    text: &str,
+    options: &Options,
+) -> Result<String, Error> {
+    let resolved = include::resolve_includes_source(input_path, text, options)?;
+    Ok(expand(&resolved, options)?.text)
+}
+
+pub fn expand(resolved: &Mapped, options: &Options) -> Result<Mapped, Error> {
+    let stripped = strip_comments_mapped(resolved, options.keep_comments);
+    let expanded = macros::expand_macros(&stripped)?;
+    let expanded = repeat::expand_repeats_mapped(&expanded)?;
+    let expanded = emit::expand_emits(&expanded)?;
+    let expanded = cells::resolve_cells(&expanded)?;
+    if options.pretty {
+        return Ok(pretty::pretty(&expanded));
+    }
     Ok(expanded)
 }
 
-fn strip_comments(text: &str) -> String {
-    let mut out = String::new();
-    for part in text.split_inclusive('\n') {
+pub fn strip_comments(text: &str) -> String {
+    strip_comments_mapped(&Mapped::plain(text), false).text
+}
+
+pub fn strip_comments_mapped(source: &Mapped, keep: bool) -> Mapped {
+    let mut out = source.derived();
+    let mut start = 0;
+    for part in source.text.split_inclusive('\n') {
         let has_newline = part.ends_with('\n');
         let mut line = &part[..part.len() - if has_newline { 1 } else { 0 }];
         if line.ends_with('\r') {
@@ -21,19 +63,55 @@ fn strip_comments(text: &str) -> String {
         }
         let trimmed = line.trim_start();
         let is_include = trimmed.starts_with("#include");
-        let mut cut = line.len();
-        if let Some(pos) = line.find("//") {
-            cut = cut.min(pos);
-        }
-        if !is_include {
-            if let Some(pos) = line.find('#') {
-                cut = cut.min(pos);
+        let cut = comment_start(line, !is_include).unwrap_or(line.len());
+        out.push_range(source, start..start + cut);
+        if keep {
+            for (offset, ch) in line[cut..].char_indices() {
+                out.push(bf_safe(ch), source.origin(start + cut + offset));
             }
         }
-        out.push_str(&line[..cut]);
         if has_newline {
-            out.push('\n');
+            let newline = start + part.len() - 1;
+            out.push_range(source, newline..newline + 1);
         }
+        start += part.len();
     }
     out
 }
+
+fn bf_safe(ch: char) -> char {
+    match ch {
+        '+' => '\u{FF0B}',
+        '-' => '\u{FF0D}',
+        '<' => '\u{FF1C}',
+        '>' => '\u{FF1E}',
+        '[' => '\u{FF3B}',
+        ']' => '\u{FF3D}',
+        '.' => '\u{FF0E}',
+        ',' => '\u{FF0C}',
+        '@' => '\u{FF20}',
+        '$' => '\u{FF04}',
+        '{' => '\u{FF5B}',
+        '}' => '\u{FF5D}',
+        '"' => '\u{FF02}',
+        _ => ch,
+    }
+}
+
+pub fn comment_start(line: &str, hash_comments: bool) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut in_string = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            b'\'' if !in_string && bytes.get(i + 2) == Some(&b'\'') => i += 2,
+            b'/' if !in_string && bytes.get(i + 1) == Some(&b'/') => return Some(i),
+            b'#' if !in_string && hash_comments => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
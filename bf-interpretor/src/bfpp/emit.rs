@@ -0,0 +1,180 @@
+use super::error::Error;
+use super::lexer::{ident_at, skip_ws};
+use super::mapped::Mapped;
+
+pub const SCRATCH_PRINT: char = '\u{E000}';
+pub const SCRATCH_SET: char = '\u{E001}';
+pub const SCRATCH_END: char = '\u{E002}';
+
+pub fn expand_emits(source: &Mapped) -> Result<Mapped, Error> {
+    let text = source.text.as_str();
+    let bytes = text.as_bytes();
+    let mut out = source.derived();
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'@' {
+            i += 1;
+            continue;
+        }
+        let directive = ident_at(text, i + 1);
+        if directive != "print" && directive != "set" {
+            i += 1;
+            continue;
+        }
+        let arg = skip_ws(text, i + 1 + directive.len());
+        let (code, next) = if directive == "print" {
+            let (data, next) = parse_string(text, arg).map_err(|err| err.at(source, arg))?;
+            (print_bytes(&data), next)
+        } else {
+            let (value, next) = parse_byte(text, arg).map_err(|err| err.at(source, arg))?;
+            (set_value(value), next)
+        };
+        out.push_range(source, copied..i);
+        if code.contains('>') {
+            let open = if directive == "print" { SCRATCH_PRINT } else { SCRATCH_SET };
+            out.push(open, source.origin(i));
+            out.push_str(&code, source.origin(i));
+            out.push(SCRATCH_END, source.origin(i));
+        } else {
+            out.push_str(&code, source.origin(i));
+        }
+        i = next;
+        copied = i;
+    }
+    out.push_range(source, copied..text.len());
+    Ok(out)
+}
+
+pub fn print_bytes(data: &[u8]) -> String {
+    let mut out = String::from("[-]");
+    let mut body = String::new();
+    let mut uses_scratch = false;
+    let mut current = 0u8;
+    for &b in data {
+        let delta = b.wrapping_sub(current);
+        let plain = plain_delta(delta);
+        let looped = loop_delta(delta);
+        match looped {
+            Some(code) if code.len() < plain.len() => {
+                body.push_str(&code);
+                uses_scratch = true;
+            }
+            _ => body.push_str(&plain),
+        }
+        body.push('.');
+        current = b;
+    }
+    if uses_scratch {
+        out.push_str(">[-]<");
+    }
+    out.push_str(&body);
+    out
+}
+
+pub fn set_value(value: u8) -> String {
+    let plain = format!("[-]{}", plain_delta(value));
+    match loop_delta(value) {
+        Some(code) if code.len() + 8 < plain.len() => format!("[-]>[-]<{}", code),
+        _ => plain,
+    }
+}
+
+fn plain_delta(delta: u8) -> String {
+    if delta <= 128 {
+        "+".repeat(delta as usize)
+    } else {
+        "-".repeat(256 - delta as usize)
+    }
+}
+
+fn loop_delta(delta: u8) -> Option<String> {
+    let (magnitude, sign) = if delta <= 128 {
+        (delta as i32, '+')
+    } else {
+        (256 - delta as i32, '-')
+    };
+    let mut best: Option<(i32, i32, i32)> = None;
+    for factor in 2..=magnitude / 2 {
+        let times = (magnitude + factor / 2) / factor;
+        let rest = magnitude - factor * times;
+        let cost = factor + times + rest.abs();
+        if best.is_none_or(|(_, _, c)| cost < c) {
+            best = Some((factor, times, cost));
+        }
+    }
+    let (factor, times, _) = best?;
+    let rest = magnitude - factor * times;
+    let mut code = String::from(">");
+    code.push_str(&"+".repeat(factor as usize));
+    code.push_str("[<");
+    code.push_str(&sign.to_string().repeat(times as usize));
+    code.push_str(">-]<");
+    let adjust = if (rest >= 0) == (sign == '+') { '+' } else { '-' };
+    code.push_str(&adjust.to_string().repeat(rest.unsigned_abs() as usize));
+    Some(code)
+}
+
+fn parse_string(text: &str, start: usize) -> Result<(Vec<u8>, usize), Error> {
+    let bytes = text.as_bytes();
+    if bytes.get(start) != Some(&b'"') {
+        return Err(emit_error("expected string literal after @print".to_string()));
+    }
+    let mut out = Vec::new();
+    let mut i = start + 1;
+    loop {
+        match bytes.get(i) {
+            None | Some(b'\n') => {
+                return Err(emit_error("unterminated string literal".to_string()));
+            }
+            Some(b'"') => return Ok((out, i + 1)),
+            Some(b'\\') => {
+                let esc = bytes.get(i + 1).copied();
+                i += 2;
+                match esc {
+                    Some(b'n') => out.push(b'\n'),
+                    Some(b't') => out.push(b'\t'),
+                    Some(b'r') => out.push(b'\r'),
+                    Some(b'0') => out.push(0),
+                    Some(b'\\') => out.push(b'\\'),
+                    Some(b'"') => out.push(b'"'),
+                    Some(b'\'') => out.push(b'\''),
+                    Some(b'x') => {
+                        let value = text
+                            .get(i..i + 2)
+                            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                            .ok_or_else(|| emit_error("invalid \\x escape".to_string()))?;
+                        out.push(value);
+                        i += 2;
+                    }
+                    _ => {
+                        return Err(emit_error("unknown escape sequence".to_string()));
+                    }
+                }
+            }
+            Some(&b) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn parse_byte(text: &str, start: usize) -> Result<(u8, usize), Error> {
+    let bytes = text.as_bytes();
+    let mut end = start;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end == start {
+        return Err(emit_error("expected value after @set".to_string()));
+    }
+    let value = text[start..end].parse::<u8>().map_err(|_| {
+        emit_error(format!("@set value {} out of range 0..255", &text[start..end]))
+    })?;
+    Ok((value, end))
+}
+
+fn emit_error(message: String) -> Error {
+    Error::EmitError { message }
+}
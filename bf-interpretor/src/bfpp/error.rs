@@ -1,6 +1,16 @@
 use std::fmt;
 use std::path::PathBuf;
 
+use super::mapped::Mapped;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+    pub source_line: String,
+}
+
 #[derive(Debug)]
 pub enum Error {
     Usage,
@@ -8,13 +18,48 @@ pub enum Error {
     WriteFailed { path: PathBuf, source: std::io::Error },
     InvalidUtf8 { path: PathBuf },
     IncludeError { path: PathBuf, message: String },
+    DirectiveError { path: PathBuf, message: String },
     RepeatError { message: String },
+    MacroError { message: String, trace: Vec<String> },
+    CellError { message: String },
+    EmitError { message: String },
+    Located { span: Span, error: Box<Error> },
+}
+
+impl Error {
+    pub fn located(self, span: Span) -> Error {
+        match self {
+            Error::Located { .. } => self,
+            error => Error::Located { span, error: Box::new(error) },
+        }
+    }
+
+    pub fn at(self, source: &Mapped, pos: usize) -> Error {
+        match source.span(pos) {
+            Some(span) => self.located(span),
+            None => self,
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Error::Located { span, .. } => Some(span),
+            _ => None,
+        }
+    }
+
+    pub fn unlocated(&self) -> &Error {
+        match self {
+            Error::Located { error, .. } => error,
+            error => error,
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Usage => write!(f, "Usage: bfpp <input.bfpp> -o <output.bf>"),
+            Error::Usage => write!(f, "Usage: bfpp <input.bfpp> -o <output.bf> [-m <output.map>] [-M <deps.d>] [-I <dir>]... [-D NAME[=value]]... [-v] [--watch] [--keep-comments] [--pretty]"),
             Error::ReadFailed { path, source } => {
                 write!(f, "failed to read '{}': {}", path.display(), source)
             }
@@ -27,9 +72,37 @@ impl fmt::Display for Error {
             Error::IncludeError { path, message } => {
                 write!(f, "include error in '{}': {}", path.display(), message)
             }
+            Error::DirectiveError { path, message } => {
+                write!(f, "directive error in '{}': {}", path.display(), message)
+            }
             Error::RepeatError { message } => {
                 write!(f, "repeat error: {}", message)
             }
+            Error::MacroError { message, trace } => {
+                write!(f, "macro error: {}", message)?;
+                for site in trace {
+                    write!(f, "\n  in expansion of {}", site)?;
+                }
+                Ok(())
+            }
+            Error::CellError { message } => {
+                write!(f, "cell error: {}", message)
+            }
+            Error::EmitError { message } => {
+                write!(f, "emit error: {}", message)
+            }
+            Error::Located { span, error } => {
+                write!(f, "{}:{}:{}: {}", span.path.display(), span.line, span.col, error)?;
+                let gutter = span.line.to_string().len();
+                let pad: String = span
+                    .source_line
+                    .bytes()
+                    .take(span.col.saturating_sub(1))
+                    .map(|b| if b == b'\t' { '\t' } else { ' ' })
+                    .collect();
+                write!(f, "\n{} | {}", span.line, span.source_line)?;
+                write!(f, "\n{:w$} | {}^", "", pad, w = gutter)
+            }
         }
     }
 }
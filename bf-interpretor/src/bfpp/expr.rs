@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+pub type ExprError = (String, usize);
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+    consts: &'a HashMap<String, i64>,
+}
+
+pub fn eval(
+    text: &str,
+    start: usize,
+    consts: &HashMap<String, i64>,
+) -> Result<(i64, usize), ExprError> {
+    let mut parser = Parser {
+        text,
+        pos: start,
+        depth: 0,
+        consts,
+    };
+    let value = parser.sum()?;
+    Ok((value, parser.pos))
+}
+
+pub fn extent(text: &str, start: usize) -> Option<usize> {
+    let consts = HashMap::new();
+    let mut parser = Parser {
+        text,
+        pos: start,
+        depth: 0,
+        consts: &consts,
+    };
+    parser.skip_sum()?;
+    Some(parser.pos)
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        let bytes = self.text.as_bytes();
+        if self.depth > 0 {
+            while self.pos < bytes.len() && bytes[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+        }
+        bytes.get(self.pos).copied()
+    }
+
+    fn sum(&mut self) -> Result<i64, ExprError> {
+        let mut value = self.product()?;
+        while let Some(op @ (b'+' | b'-')) = self.peek() {
+            let at = self.pos;
+            self.pos += 1;
+            let rhs = self.product()?;
+            let result = if op == b'+' {
+                value.checked_add(rhs)
+            } else {
+                value.checked_sub(rhs)
+            };
+            value = result.ok_or_else(|| ("arithmetic overflow".to_string(), at))?;
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, ExprError> {
+        let mut value = self.unary()?;
+        while let Some(op @ (b'*' | b'/' | b'%')) = self.peek() {
+            let at = self.pos;
+            self.pos += 1;
+            let rhs = self.unary()?;
+            if op != b'*' && rhs == 0 {
+                return Err(("division by zero".to_string(), at));
+            }
+            let result = match op {
+                b'*' => value.checked_mul(rhs),
+                b'/' => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            };
+            value = result.ok_or_else(|| ("arithmetic overflow".to_string(), at))?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, ExprError> {
+        let start = self.pos;
+        match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                let value = self.unary()?;
+                value
+                    .checked_neg()
+                    .ok_or_else(|| ("arithmetic overflow".to_string(), start))
+            }
+            Some(b'(') => {
+                let open = self.pos;
+                self.pos += 1;
+                self.depth += 1;
+                let value = self.sum()?;
+                if self.peek() != Some(b')') {
+                    return Err(("missing ')'".to_string(), open));
+                }
+                self.depth -= 1;
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(b'\'') => self.char_literal(),
+            Some(b) if b.is_ascii_digit() => {
+                let digits = self.take_while(|b| b.is_ascii_digit());
+                digits
+                    .parse()
+                    .map_err(|_| ("number too large".to_string(), self.pos - digits.len()))
+            }
+            Some(b) if b.is_ascii_alphabetic() || b == b'_' => {
+                let start = self.pos;
+                let name = self.take_while(|b| b.is_ascii_alphanumeric() || b == b'_');
+                self.consts
+                    .get(name)
+                    .copied()
+                    .ok_or_else(|| (format!("unknown constant '{}'", name), start))
+            }
+            _ => Err(("expected number, character or constant".to_string(), self.pos)),
+        }
+    }
+
+    fn skip_sum(&mut self) -> Option<()> {
+        self.skip_unary()?;
+        while let Some(b'+' | b'-' | b'*' | b'/' | b'%') = self.peek() {
+            self.pos += 1;
+            self.skip_unary()?;
+        }
+        Some(())
+    }
+
+    fn skip_unary(&mut self) -> Option<()> {
+        match self.peek()? {
+            b'-' => {
+                self.pos += 1;
+                self.skip_unary()
+            }
+            b'(' => {
+                self.pos += 1;
+                self.depth += 1;
+                self.skip_sum()?;
+                if self.peek() != Some(b')') {
+                    return None;
+                }
+                self.depth -= 1;
+                self.pos += 1;
+                Some(())
+            }
+            b'\'' => self.char_literal().ok().map(|_| ()),
+            b if b.is_ascii_alphanumeric() || b == b'_' => {
+                self.take_while(|b| b.is_ascii_alphanumeric() || b == b'_');
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    fn char_literal(&mut self) -> Result<i64, ExprError> {
+        let bytes = self.text.as_bytes();
+        let start = self.pos;
+        let invalid = || ("invalid character literal".to_string(), start);
+        let (value, len) = match bytes.get(start + 1) {
+            Some(b'\\') => match bytes.get(start + 2) {
+                Some(b'n') => (b'\n', 2),
+                Some(b't') => (b'\t', 2),
+                Some(b'r') => (b'\r', 2),
+                Some(b'0') => (0, 2),
+                Some(&b @ (b'\\' | b'\'' | b'"')) => (b, 2),
+                Some(b'x') => {
+                    let value = self
+                        .text
+                        .get(start + 3..start + 5)
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        .ok_or_else(invalid)?;
+                    (value, 4)
+                }
+                _ => return Err(invalid()),
+            },
+            Some(&b) if b.is_ascii() && b != b'\'' && b != b'\n' => (b, 1),
+            _ => return Err(invalid()),
+        };
+        if bytes.get(start + 1 + len) != Some(&b'\'') {
+            return Err(invalid());
+        }
+        self.pos = start + len + 2;
+        Ok(value as i64)
+    }
+
+    fn take_while(&mut self, pred: impl Fn(u8) -> bool) -> &'a str {
+        let bytes = self.text.as_bytes();
+        let start = self.pos;
+        while self.pos < bytes.len() && pred(bytes[self.pos]) {
+            self.pos += 1;
+        }
+        &self.text[start..self.pos]
+    }
+}
@@ -1,86 +1,420 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use super::error::Error;
+use super::driver::{comment_start, Options};
+use super::error::{Error, Span};
+use super::mapped::Mapped;
+use super::stdlib;
 
-pub fn resolve_includes(input_path: &Path) -> Result<String, Error> {
-    let mut stack = Vec::new();
-    resolve_path(input_path, &mut stack)
+const NAME_DIRECTIVES: &[&str] =
+    &["var", "scratch", "at", "goto", "if", "while", "times", "assert_pos", "const", "macro"];
+
+pub enum Include {
+    Local(PathBuf),
+    Library(PathBuf),
 }
 
-fn resolve_path(path: &Path, stack: &mut Vec<PathBuf>) -> Result<String, Error> {
-    let canonical = std::fs::canonicalize(path)
-        .map_err(|e| Error::ReadFailed { path: path.to_path_buf(), source: e })?;
-    if let Some(pos) = stack.iter().position(|p| p == &canonical) {
-        let mut cycle = String::new();
-        for (i, p) in stack[pos..].iter().enumerate() {
-            if i > 0 {
-                cycle.push_str(" -> ");
+struct Resolver<'a> {
+    options: &'a Options,
+    stack: Vec<PathBuf>,
+    sites: Vec<String>,
+    once: HashSet<PathBuf>,
+    defines: HashMap<String, String>,
+    out: Mapped,
+}
+
+pub fn resolve_includes(input_path: &Path, options: &Options) -> Result<Mapped, Error> {
+    let mut resolver = Resolver::new(options);
+    resolver.resolve_path(input_path)?;
+    Ok(resolver.out)
+}
+
+pub fn resolve_includes_source(
+    input_path: &Path,
+    text: &str,
+    options: &Options,
+) -> Result<Mapped, Error> {
+    let mut resolver = Resolver::new(options);
+    let key = std::fs::canonicalize(input_path).unwrap_or_else(|_| input_path.to_path_buf());
+    resolver.resolve_file(input_path, key, text)?;
+    Ok(resolver.out)
+}
+
+pub fn env_search_path() -> Vec<PathBuf> {
+    env::var_os("BFPP_PATH")
+        .map(|value| env::split_paths(&value).collect())
+        .unwrap_or_default()
+}
+
+pub fn dependencies(resolved: &Mapped) -> Vec<PathBuf> {
+    let mut deps: Vec<PathBuf> = Vec::new();
+    for path in &resolved.files {
+        if !path.starts_with(stdlib::PREFIX) && !deps.contains(path) {
+            deps.push(path.clone());
+        }
+    }
+    deps
+}
+
+pub fn find_library(name: &Path, search: &[PathBuf]) -> Option<PathBuf> {
+    search.iter().map(|dir| dir.join(name)).find(|path| path.is_file())
+}
+
+fn is_pragma_once(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    words.next() == Some("#pragma")
+        && words.next() == Some("once")
+        && words.next().is_none_or(|w| w.starts_with("//") || w.starts_with('#'))
+}
+
+impl<'a> Resolver<'a> {
+    fn new(options: &'a Options) -> Resolver<'a> {
+        Resolver {
+            options,
+            stack: Vec::new(),
+            sites: Vec::new(),
+            once: HashSet::new(),
+            defines: options.defines.iter().cloned().collect(),
+            out: Mapped::default(),
+        }
+    }
+
+    fn resolve_path(&mut self, path: &Path) -> Result<(), Error> {
+        let canonical = std::fs::canonicalize(path)
+            .map_err(|e| Error::ReadFailed { path: path.to_path_buf(), source: e })?;
+        if self.skip_once(path, &canonical) {
+            return Ok(());
+        }
+        self.check_cycle(path, &canonical)?;
+
+        let bytes = std::fs::read(path)
+            .map_err(|e| Error::ReadFailed { path: path.to_path_buf(), source: e })?;
+        let text = String::from_utf8(bytes)
+            .map_err(|_| Error::InvalidUtf8 { path: path.to_path_buf() })?;
+
+        self.resolve_file(path, canonical, &text)
+    }
+
+    fn resolve_library(&mut self, name: &Path, from: &Path) -> Result<(), Error> {
+        if let Some(path) = find_library(name, &self.options.include_dirs) {
+            return self.resolve_path(&path);
+        }
+        let text = name.to_str().and_then(stdlib::lookup).ok_or_else(|| Error::IncludeError {
+            path: from.to_path_buf(),
+            message: format!("library <{}> not found in search path", name.display()),
+        })?;
+        let bundled = Path::new(stdlib::PREFIX).join(name);
+        if self.skip_once(&bundled, &bundled) {
+            return Ok(());
+        }
+        self.check_cycle(&bundled, &bundled)?;
+        self.resolve_file(&bundled, bundled.clone(), text)
+    }
+
+    fn skip_once(&self, path: &Path, key: &Path) -> bool {
+        if !self.once.contains(key) {
+            return false;
+        }
+        if self.options.verbose {
+            eprintln!("note: skipping '{}' (#pragma once)", path.display());
+            for site in self.sites.iter().rev() {
+                eprintln!("  included from {}", site);
             }
-            cycle.push_str(&p.display().to_string());
         }
-        if !cycle.is_empty() {
-            cycle.push_str(" -> ");
+        true
+    }
+
+    fn check_cycle(&self, path: &Path, canonical: &Path) -> Result<(), Error> {
+        if let Some(pos) = self.stack.iter().position(|p| p == canonical) {
+            let mut cycle = String::new();
+            for (i, p) in self.stack[pos..].iter().enumerate() {
+                if i > 0 {
+                    cycle.push_str(" -> ");
+                }
+                cycle.push_str(&p.display().to_string());
+            }
+            if !cycle.is_empty() {
+                cycle.push_str(" -> ");
+            }
+            cycle.push_str(&canonical.display().to_string());
+            return Err(Error::IncludeError {
+                path: path.to_path_buf(),
+                message: format!("include cycle: {}", cycle),
+            });
         }
-        cycle.push_str(&canonical.display().to_string());
-        return Err(Error::IncludeError {
-            path: path.to_path_buf(),
-            message: format!("include cycle: {}", cycle),
-        });
+        Ok(())
     }
-    stack.push(canonical);
 
-    let bytes = std::fs::read(path)
-        .map_err(|e| Error::ReadFailed { path: path.to_path_buf(), source: e })?;
-    let text = String::from_utf8(bytes)
-        .map_err(|_| Error::InvalidUtf8 { path: path.to_path_buf() })?;
+    fn resolve_file(&mut self, path: &Path, key: PathBuf, text: &str) -> Result<(), Error> {
+        self.stack.push(key);
+        self.resolve_text(path, text)?;
+        self.stack.pop();
+        Ok(())
+    }
 
-    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
-    let mut out = String::new();
+    fn resolve_text(&mut self, path: &Path, text: &str) -> Result<(), Error> {
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let file = self.out.files.len();
+        self.out.files.push(path.to_path_buf());
+        self.out.sources.push(Rc::from(text));
+        let source = Mapped::from_file(text, file, Vec::new());
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut start = 0;
 
-    for part in text.split_inclusive('\n') {
-        let has_newline = part.ends_with('\n');
-        let mut line = &part[..part.len() - if has_newline { 1 } else { 0 }];
-        if line.ends_with('\r') {
-            line = &line[..line.len() - 1];
+        for (index, part) in text.split_inclusive('\n').enumerate() {
+            let has_newline = part.ends_with('\n');
+            let newline = start + part.len() - 1;
+            let mut line = &part[..part.len() - if has_newline { 1 } else { 0 }];
+            if line.ends_with('\r') {
+                line = &line[..line.len() - 1];
+            }
+            let trimmed = line.trim_start();
+            let active = conditions.iter().all(|c| c.taking);
+            let line_number = index + 1;
+            let col = line.len() - trimmed.len() + 1;
+            let span = || line_span(path, line, line_number, col);
+            let directive = self
+                .conditional(trimmed, &mut conditions, path, line_number)
+                .map_err(|err| err.located(span()))?;
+            if directive || !active {
+                if has_newline {
+                    self.out.push_range(&source, newline..newline + 1);
+                }
+                start += part.len();
+                continue;
+            }
+            if trimmed.starts_with("#include") {
+                let include = parse_include(trimmed, path).map_err(|err| err.located(span()))?;
+                let include_path = match &include {
+                    Include::Local(include_path) | Include::Library(include_path) => include_path,
+                };
+                if include_path.is_absolute() {
+                    return Err(Error::IncludeError {
+                        path: path.to_path_buf(),
+                        message: "include path must be relative".to_string(),
+                    }
+                    .located(span()));
+                }
+                let before = self.out.len();
+                self.sites.push(format!("{}:{}", path.display(), line_number));
+                match &include {
+                    Include::Local(include_path) => self.resolve_path(&base_dir.join(include_path)),
+                    Include::Library(name) => self.resolve_library(name, path),
+                }
+                .map_err(|err| err.located(span()))?;
+                self.sites.pop();
+                if has_newline && !self.out.text[before..].ends_with('\n') {
+                    self.out.push('\n', source.origin(newline));
+                }
+            } else {
+                let once = is_pragma_once(trimmed);
+                if once {
+                    self.once.extend(self.stack.last().cloned());
+                }
+                let defined = !once
+                    && !self
+                        .define(trimmed, path)
+                        .map_err(|err| err.located(span()))?;
+                if defined {
+                    let offset = start + (line.len() - trimmed.len());
+                    self.out.push_range(&source, start..offset);
+                    self.substitute(&source, offset..start + line.len());
+                }
+                if has_newline {
+                    self.out.push_range(&source, newline..newline + 1);
+                }
+            }
+            start += part.len();
         }
-        let trimmed = line.trim_start();
-        if trimmed.starts_with("#include") {
-            let include_path = parse_include_path(trimmed, path)?;
-            if include_path.is_absolute() {
-                return Err(Error::IncludeError {
-                    path: path.to_path_buf(),
-                    message: "include path must be relative".to_string(),
+
+        if let Some(open) = conditions.last() {
+            let line = text.lines().nth(open.line - 1).unwrap_or("");
+            let col = line.len() - line.trim_start().len() + 1;
+            return Err(Error::DirectiveError {
+                path: path.to_path_buf(),
+                message: format!("unterminated {}", open.directive),
+            }
+            .located(line_span(path, line, open.line, col)));
+        }
+        Ok(())
+    }
+
+    fn conditional(
+        &mut self,
+        line: &str,
+        conditions: &mut Vec<Condition>,
+        path: &Path,
+        line_number: usize,
+    ) -> Result<bool, Error> {
+        let words = directive_words(line);
+        let error = |message: String| Error::DirectiveError {
+            path: path.to_path_buf(),
+            message,
+        };
+        match words.first().copied() {
+            Some(directive @ ("#ifdef" | "#ifndef")) => {
+                let name = match words[..] {
+                    [_, name] => name,
+                    _ => return Err(error(format!("{} expects one name", directive))),
+                };
+                let defined = self.defines.contains_key(name);
+                let ifdef = directive == "#ifdef";
+                conditions.push(Condition {
+                    directive: if ifdef { "#ifdef" } else { "#ifndef" },
+                    line: line_number,
+                    taking: defined == ifdef,
+                    seen_else: false,
                 });
             }
-            let full_path = base_dir.join(include_path);
-            let resolved = resolve_path(&full_path, stack)?;
-            out.push_str(&resolved);
-            if has_newline && !resolved.ends_with('\n') {
-                out.push('\n');
+            Some("#else") => {
+                let open = conditions
+                    .last_mut()
+                    .ok_or_else(|| error("#else without #ifdef".to_string()))?;
+                if open.seen_else {
+                    return Err(error(format!("duplicate #else for {}", open.directive)));
+                }
+                open.seen_else = true;
+                open.taking = !open.taking;
             }
-        } else {
-            out.push_str(line);
-            if has_newline {
-                out.push('\n');
+            Some("#endif") => {
+                conditions
+                    .pop()
+                    .ok_or_else(|| error("#endif without #ifdef".to_string()))?;
             }
+            _ => return Ok(false),
         }
+        Ok(true)
+    }
+
+    fn define(&mut self, line: &str, path: &Path) -> Result<bool, Error> {
+        let words = directive_words(line);
+        match words.first().copied() {
+            Some("#define") if words.len() >= 2 && is_identifier(words[1]) => {
+                let value = words[2..].join(" ");
+                let value = self.expand_value(&value);
+                self.defines.insert(words[1].to_string(), value);
+            }
+            Some("#undef") if words.len() == 2 => {
+                self.defines.remove(words[1]);
+            }
+            Some(directive @ ("#define" | "#undef")) => {
+                return Err(Error::DirectiveError {
+                    path: path.to_path_buf(),
+                    message: format!("invalid {}", directive),
+                });
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn expand_value(&self, value: &str) -> String {
+        let source = Mapped::plain(value);
+        let mut out = source.derived();
+        replace_names(&self.defines, &source, 0..value.len(), &mut out);
+        out.text
+    }
+
+    fn substitute(&mut self, source: &Mapped, range: std::ops::Range<usize>) {
+        replace_names(&self.defines, source, range, &mut self.out);
+    }
+}
+
+fn line_span(path: &Path, line: &str, line_number: usize, col: usize) -> Span {
+    Span {
+        path: path.to_path_buf(),
+        line: line_number,
+        col,
+        source_line: line.to_string(),
+    }
+}
+
+struct Condition {
+    directive: &'static str,
+    line: usize,
+    taking: bool,
+    seen_else: bool,
+}
+
+fn directive_words(line: &str) -> Vec<&str> {
+    if !line.starts_with('#') {
+        return Vec::new();
     }
+    let end = comment_start(&line[1..], true).map_or(line.len(), |pos| pos + 1);
+    line[..end].split_whitespace().collect()
+}
+
+pub fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
 
-    stack.pop();
-    Ok(out)
+fn replace_names(
+    defines: &HashMap<String, String>,
+    source: &Mapped,
+    range: std::ops::Range<usize>,
+    out: &mut Mapped,
+) {
+    let bytes = source.text.as_bytes();
+    let mut in_string = false;
+    let mut name_arg = false;
+    let mut copied = range.start;
+    let mut i = range.start;
+    while i < range.end {
+        let b = bytes[i];
+        if b == b'"' {
+            in_string = !in_string;
+        } else if b == b'\\' && in_string {
+            i += 1;
+        } else if !in_string && b == b'/' && bytes.get(i + 1) == Some(&b'/') {
+            while i < range.end && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        } else if !in_string && (b.is_ascii_alphabetic() || b == b'_') {
+            let mut end = i;
+            while end < range.end && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_') {
+                end += 1;
+            }
+            let word = &source.text[i..end];
+            let prefixed = i > range.start && matches!(bytes[i - 1], b'@' | b'$');
+            let glued = i > range.start && bytes[i - 1].is_ascii_digit();
+            if let Some(value) = defines.get(word) {
+                if !prefixed && !glued && !name_arg && !value.is_empty() {
+                    out.push_range(source, copied..i);
+                    out.push_str(value, source.origin(i));
+                    copied = end;
+                }
+            }
+            name_arg = i > range.start && bytes[i - 1] == b'@' && NAME_DIRECTIVES.contains(&word);
+            i = end;
+            continue;
+        } else if !b.is_ascii_whitespace() {
+            name_arg = false;
+        }
+        i += 1;
+    }
+    out.push_range(source, copied..range.end);
 }
 
-fn parse_include_path(line: &str, path: &Path) -> Result<PathBuf, Error> {
+pub fn parse_include(line: &str, path: &Path) -> Result<Include, Error> {
     let rest = &line["#include".len()..];
     let rest = rest.trim_start();
-    if !rest.starts_with('"') {
-        return Err(Error::IncludeError {
-            path: path.to_path_buf(),
-            message: "invalid include syntax".to_string(),
-        });
-    }
+    let close = match rest.chars().next() {
+        Some('"') => '"',
+        Some('<') => '>',
+        _ => {
+            return Err(Error::IncludeError {
+                path: path.to_path_buf(),
+                message: "invalid include syntax".to_string(),
+            })
+        }
+    };
     let rest = &rest[1..];
-    let end = match rest.find('"') {
+    let end = match rest.find(close) {
         Some(value) => value,
         None => {
             return Err(Error::IncludeError {
@@ -104,5 +438,9 @@ fn parse_include_path(line: &str, path: &Path) -> Result<PathBuf, Error> {
             message: "invalid include syntax".to_string(),
         });
     }
-    Ok(PathBuf::from(path_str))
+    if close == '>' {
+        Ok(Include::Library(PathBuf::from(path_str)))
+    } else {
+        Ok(Include::Local(PathBuf::from(path_str)))
+    }
 }
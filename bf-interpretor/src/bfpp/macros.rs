@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::error::Error;
+use super::lexer::{ident_at, matching, skip_ws, unquoted};
+use super::mapped::Mapped;
+
+const MAX_DEPTH: usize = 32;
+const RESERVED: &[&str] = &["macro", "repeat", "var", "at", "goto", "print", "set", "scratch", "if", "else", "while", "times", "const", "balanced", "assert_pos"];
+
+struct Macro {
+    params: Vec<String>,
+    body: Mapped,
+    line: usize,
+}
+
+pub fn expand_macros(source: &Mapped) -> Result<Mapped, Error> {
+    let (rest, macros) = collect_definitions(source)?;
+    let mut trace = Vec::new();
+    expand(&rest, &macros, &mut trace)
+}
+
+fn collect_definitions(source: &Mapped) -> Result<(Mapped, HashMap<String, Macro>), Error> {
+    let text = source.text.as_str();
+    let bytes = text.as_bytes();
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut out = source.derived();
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'@' || ident_at(text, i + 1) != "macro" {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let (name, mac, close) =
+            parse_definition(source, start).map_err(|err| err.at(source, start))?;
+        if let Some(existing) = macros.get(name) {
+            return Err(macro_error(
+                format!(
+                    "macro '{}' redefined (first defined on line {})",
+                    name, existing.line
+                ),
+                &[],
+            )
+            .at(source, start));
+        }
+        macros.insert(name.to_string(), mac);
+
+        out.push_range(source, copied..start);
+        for (pos, _) in text[start..close].match_indices('\n') {
+            out.push_range(source, start + pos..start + pos + 1);
+        }
+        i = close + 1;
+        copied = i;
+    }
+    out.push_range(source, copied..text.len());
+    Ok((out, macros))
+}
+
+fn parse_definition(source: &Mapped, start: usize) -> Result<(&str, Macro, usize), Error> {
+    let text = source.text.as_str();
+    let bytes = text.as_bytes();
+    let line = source.origin(start).line;
+    let mut i = skip_ws(text, start + 1 + "macro".len());
+    let name = ident_at(text, i);
+    if name.is_empty() {
+        return Err(macro_error("expected macro name".to_string(), &[]));
+    }
+    if RESERVED.contains(&name) {
+        return Err(macro_error(
+            format!("'{}' is a reserved directive name", name),
+            &[],
+        ));
+    }
+    i += name.len();
+    if bytes.get(i) != Some(&b'(') {
+        return Err(macro_error(
+            format!("expected '(' after macro name '{}'", name),
+            &[],
+        ));
+    }
+    let (params, next) = parse_list(text, i)?;
+    let params: Vec<String> = params.into_iter().map(|r| text[r].to_string()).collect();
+    for (idx, param) in params.iter().enumerate() {
+        if ident_at(param, 0) != param {
+            return Err(macro_error(
+                format!("invalid parameter '{}' for macro '{}'", param, name),
+                &[],
+            ));
+        }
+        if params[..idx].contains(param) {
+            return Err(macro_error(
+                format!("duplicate parameter '{}' for macro '{}'", param, name),
+                &[],
+            ));
+        }
+    }
+    i = skip_ws(text, next);
+    if bytes.get(i) != Some(&b'{') {
+        return Err(macro_error(
+            format!("expected '{{' to open body of macro '{}'", name),
+            &[],
+        ));
+    }
+    let close = matching(text, i, b'{', b'}').ok_or_else(|| {
+        macro_error(format!("missing '}}' for macro '{}'", name), &[])
+    })?;
+    let body = source.slice(i + 1..close);
+    Ok((name, Macro { params, body, line }, close))
+}
+
+fn expand(
+    source: &Mapped,
+    macros: &HashMap<String, Macro>,
+    trace: &mut Vec<String>,
+) -> Result<Mapped, Error> {
+    let text = source.text.as_str();
+    let bytes = text.as_bytes();
+    let mut out = source.derived();
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'@' {
+            i += 1;
+            continue;
+        }
+        let name = ident_at(text, i + 1);
+        let open = i + 1 + name.len();
+        if name.is_empty() || RESERVED.contains(&name) || bytes.get(open) != Some(&b'(') {
+            i += 1;
+            continue;
+        }
+        let site = match source.span(i) {
+            Some(span) => {
+                format!("@{} at {}:{}:{}", name, span.path.display(), span.line, span.col)
+            }
+            None => format!("@{}", name),
+        };
+        let mac = macros
+            .get(name)
+            .ok_or_else(|| macro_error(format!("unknown macro '{}'", name), trace))
+            .map_err(|err| err.at(source, i))?;
+        let (args, next) = parse_list(text, open).map_err(|err| err.at(source, i))?;
+        let args: Vec<Mapped> = args.into_iter().map(|r| unescape(source, r)).collect();
+        if args.len() != mac.params.len() {
+            let err = macro_error(
+                format!(
+                    "macro '{}' expects {} argument(s), got {}",
+                    name,
+                    mac.params.len(),
+                    args.len()
+                ),
+                trace,
+            );
+            return Err(err.at(source, i));
+        }
+        if trace.len() >= MAX_DEPTH {
+            return Err(macro_error(
+                format!("macro expansion depth limit ({}) exceeded", MAX_DEPTH),
+                trace,
+            )
+            .at(source, i));
+        }
+
+        trace.push(site);
+        let body = substitute(&mac.body, &mac.params, &args, name, trace)?;
+        let expanded = expand(&body, macros, trace)?;
+        trace.pop();
+
+        out.push_range(source, copied..i);
+        out.push_mapped(&expanded);
+        i = next;
+        copied = i;
+    }
+    out.push_range(source, copied..text.len());
+    Ok(out)
+}
+
+fn substitute(
+    body: &Mapped,
+    params: &[String],
+    args: &[Mapped],
+    name: &str,
+    trace: &[String],
+) -> Result<Mapped, Error> {
+    let text = body.text.as_str();
+    let mut out = body.derived();
+    let mut copied = 0;
+    while let Some(pos) = text[copied..].find('$').map(|p| p + copied) {
+        out.push_range(body, copied..pos);
+        let ident = ident_at(text, pos + 1);
+        let idx = params.iter().position(|p| p == ident).ok_or_else(|| {
+            macro_error(
+                format!("unknown parameter '${}' in macro '{}'", ident, name),
+                trace,
+            )
+            .at(body, pos)
+        })?;
+        out.push_mapped(&args[idx]);
+        copied = pos + 1 + ident.len();
+    }
+    out.push_range(body, copied..text.len());
+    Ok(out)
+}
+
+fn parse_list(text: &str, open: usize) -> Result<(Vec<Range<usize>>, usize), Error> {
+    let close = matching(text, open, b'(', b')')
+        .ok_or_else(|| macro_error("missing ')'".to_string(), &[]))?;
+    let mut items = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (idx, b) in unquoted(&text[..close], open + 1) {
+        let escaped = text.as_bytes()[idx - 1] == b'\\';
+        let idx = idx - open - 1;
+        match b {
+            b'(' | b'{' | b'[' => depth += 1,
+            b')' | b'}' | b']' => depth -= 1,
+            b',' if depth == 0 && !escaped => {
+                items.push(trimmed(text, open + 1 + start..open + 1 + idx));
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = trimmed(text, open + 1 + start..close);
+    if !(items.is_empty() && last.is_empty()) {
+        items.push(last);
+    }
+    Ok((items, close + 1))
+}
+
+fn unescape(source: &Mapped, range: Range<usize>) -> Mapped {
+    let text = source.text.as_str();
+    let mut out = source.derived();
+    let mut copied = range.start;
+    for (idx, b) in unquoted(&text[..range.end], range.start) {
+        if b == b'\\' && text.as_bytes().get(idx + 1) == Some(&b',') {
+            out.push_range(source, copied..idx);
+            copied = idx + 1;
+        }
+    }
+    out.push_range(source, copied..range.end);
+    out
+}
+
+fn trimmed(text: &str, range: Range<usize>) -> Range<usize> {
+    let slice = &text[range.clone()];
+    let start = range.start + (slice.len() - slice.trim_start().len());
+    let end = range.end - (slice.len() - slice.trim_end().len());
+    start..end.max(start)
+}
+
+fn macro_error(message: String, trace: &[String]) -> Error {
+    Error::MacroError {
+        message,
+        trace: trace.iter().rev().cloned().collect(),
+    }
+}
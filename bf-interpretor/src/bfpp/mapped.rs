@@ -0,0 +1,120 @@
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use super::error::Span;
+use crate::srcmap::SourceMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Origin {
+    pub file: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mapped {
+    pub text: String,
+    pub origins: Vec<Origin>,
+    pub files: Vec<PathBuf>,
+    pub sources: Vec<Rc<str>>,
+}
+
+impl Mapped {
+    pub fn plain(text: &str) -> Mapped {
+        let mut mapped = Mapped::from_file(text, 0, vec![PathBuf::new()]);
+        mapped.sources.push(Rc::from(text));
+        mapped
+    }
+
+    pub fn from_file(text: &str, file: usize, files: Vec<PathBuf>) -> Mapped {
+        let mut origins = Vec::with_capacity(text.len());
+        let (mut line, mut col) = (1, 1);
+        for b in text.bytes() {
+            origins.push(Origin { file, line, col });
+            if b == b'\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+        }
+        Mapped {
+            text: text.to_string(),
+            origins,
+            files,
+            sources: Vec::new(),
+        }
+    }
+
+    pub fn derived(&self) -> Mapped {
+        Mapped {
+            text: String::new(),
+            origins: Vec::new(),
+            files: self.files.clone(),
+            sources: self.sources.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn origin(&self, pos: usize) -> Origin {
+        self.origins
+            .get(pos)
+            .or_else(|| self.origins.last())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn span(&self, pos: usize) -> Option<Span> {
+        let origin = self.origins.get(pos).or_else(|| self.origins.last())?;
+        let source = self.sources.get(origin.file)?;
+        Some(Span {
+            path: self.files.get(origin.file)?.clone(),
+            line: origin.line,
+            col: origin.col,
+            source_line: source.lines().nth(origin.line - 1).unwrap_or("").to_string(),
+        })
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Mapped {
+        let mut out = self.derived();
+        out.push_range(self, range);
+        out
+    }
+
+    pub fn push_range(&mut self, src: &Mapped, range: Range<usize>) {
+        self.text.push_str(&src.text[range.clone()]);
+        self.origins.extend_from_slice(&src.origins[range]);
+    }
+
+    pub fn push_mapped(&mut self, src: &Mapped) {
+        self.push_range(src, 0..src.len());
+    }
+
+    pub fn push_str(&mut self, text: &str, origin: Origin) {
+        self.text.push_str(text);
+        self.origins.extend(std::iter::repeat_n(origin, text.len()));
+    }
+
+    pub fn push(&mut self, ch: char, origin: Origin) {
+        self.text.push(ch);
+        self.origins.extend(std::iter::repeat_n(origin, ch.len_utf8()));
+    }
+
+    pub fn source_map(&self) -> SourceMap {
+        let files = self.files.iter().map(|path| path.display().to_string()).collect();
+        let origins: Vec<_> = self
+            .origins
+            .iter()
+            .map(|origin| (origin.file, origin.line, origin.col))
+            .collect();
+        SourceMap::build(files, &origins)
+    }
+}
@@ -1,8 +1,16 @@
+pub mod cells;
 pub mod driver;
+pub mod emit;
 pub mod error;
+pub mod expr;
 pub mod include;
+pub mod lexer;
+pub mod macros;
+pub mod mapped;
+pub mod pretty;
 pub mod repeat;
+pub mod stdlib;
 
-pub use driver::preprocess;
+pub use driver::{preprocess, Options};
 pub use error::Error;
 pub use include::resolve_includes;
@@ -0,0 +1,84 @@
+use super::mapped::Mapped;
+
+const INDENT: &str = "    ";
+const WIDTH: usize = 80;
+
+struct Layout<'a> {
+    source: &'a Mapped,
+    out: Mapped,
+    line: Mapped,
+    depth: usize,
+    space: Option<usize>,
+}
+
+impl Layout<'_> {
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let origin = self.line.origin(0);
+            for _ in 0..self.depth {
+                self.out.push_str(INDENT, origin);
+            }
+            self.out.push_mapped(&self.line);
+            self.out.push('\n', self.line.origin(self.line.len() - 1));
+            self.line = self.source.derived();
+        }
+        self.space = None;
+    }
+
+    fn push(&mut self, pos: usize, len: usize) {
+        if let Some(space) = self.space.take() {
+            let word = self.source.text[pos..]
+                .chars()
+                .take_while(|ch| !ch.is_whitespace() && !"+-<>[].,".contains(*ch))
+                .count()
+                .max(1);
+            if self.width() + 1 + word > WIDTH {
+                self.flush();
+            } else if !self.line.is_empty() {
+                self.line.push(' ', self.source.origin(space));
+            }
+        }
+        self.line.push_range(self.source, pos..pos + len);
+    }
+
+    fn width(&self) -> usize {
+        self.depth * INDENT.len() + self.line.text.chars().count()
+    }
+}
+
+pub fn pretty(source: &Mapped) -> Mapped {
+    let mut layout = Layout {
+        source,
+        out: source.derived(),
+        line: source.derived(),
+        depth: 0,
+        space: None,
+    };
+    for (pos, ch) in source.text.char_indices() {
+        match ch {
+            '[' => {
+                layout.flush();
+                layout.push(pos, 1);
+                layout.flush();
+                layout.depth += 1;
+            }
+            ']' => {
+                layout.flush();
+                layout.depth = layout.depth.saturating_sub(1);
+                layout.push(pos, 1);
+                layout.flush();
+            }
+            '\n' => layout.flush(),
+            ' ' | '\t' | '\r' => layout.space = Some(pos),
+            '+' | '-' | '<' | '>' | '.' | ',' => {
+                if layout.width() >= WIDTH {
+                    layout.flush();
+                }
+                layout.push(pos, 1);
+            }
+            _ => layout.push(pos, ch.len_utf8()),
+        }
+    }
+    layout.flush();
+    layout.out
+}
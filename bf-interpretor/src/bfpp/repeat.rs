@@ -1,118 +1,118 @@
+use std::collections::HashMap;
+
 use super::error::Error;
+use super::expr;
+use super::lexer::{ident_at, skip_ws};
+use super::mapped::Mapped;
 
 pub fn expand_repeats(text: &str) -> Result<String, Error> {
-    let (out, _) = parse_section(text, 0, None)?;
+    Ok(expand_repeats_mapped(&Mapped::plain(text))?.text)
+}
+
+pub fn expand_repeats_mapped(source: &Mapped) -> Result<Mapped, Error> {
+    let mut consts = HashMap::new();
+    let (out, _) = parse_section(source, 0, None, &mut consts)?;
     Ok(out)
 }
 
-fn parse_section(text: &str, mut i: usize, end_char: Option<u8>) -> Result<(String, usize), Error> {
+fn parse_section(
+    source: &Mapped,
+    mut i: usize,
+    end_char: Option<u8>,
+    consts: &mut HashMap<String, i64>,
+) -> Result<(Mapped, usize), Error> {
+    let text = source.text.as_str();
     let bytes = text.as_bytes();
-    let mut out = String::new();
+    let mut out = source.derived();
+    let open = i.saturating_sub(1);
     while i < bytes.len() {
         if let Some(end) = end_char {
             if bytes[i] == end {
                 return Ok((out, i + 1));
             }
         }
+        if bytes[i] == b'@' && is_directive(text, i, "@const") {
+            let start = i;
+            let name_start = skip_ws(text, i + "@const".len());
+            let name = ident_at(text, name_start);
+            if name.is_empty() {
+                return Err(repeat_error(source, "expected constant name after @const", start));
+            }
+            if consts.contains_key(name) {
+                let message = format!("constant '{}' redefined", name);
+                return Err(repeat_error(source, &message, name_start));
+            }
+            let next_i = skip_ws(text, name_start + name.len());
+            if next_i == name_start + name.len() {
+                return Err(repeat_error(source, "invalid @const syntax", start));
+            }
+            let (value, next_i) = expr::eval(text, next_i, consts)
+                .map_err(|(message, at)| repeat_error(source, &message, at))?;
+            consts.insert(name.to_string(), value);
+            i = next_i;
+            continue;
+        }
         if bytes[i] == b'@' && text[i..].starts_with("@repeat") {
             let start = i;
             i += "@repeat".len();
-            let (next_i, had_ws) = skip_ws(text, i);
-            if !had_ws {
-                return Err(Error::RepeatError {
-                    message: format!("invalid @repeat syntax at byte {}", start),
-                });
+            let next_i = skip_ws(text, i);
+            if next_i == i {
+                return Err(repeat_error(source, "invalid @repeat syntax", start));
             }
             i = next_i;
-            let (count, next_i) = parse_number(text, i)?;
-            if count == 0 {
-                return Err(Error::RepeatError {
-                    message: format!("repeat count must be positive at byte {}", i),
-                });
+            let (count, next_i) = expr::eval(text, i, consts)
+                .map_err(|(message, at)| repeat_error(source, &message, at))?;
+            if count <= 0 {
+                return Err(repeat_error(source, "repeat count must be positive", i));
             }
             i = next_i;
-            let (next_i, had_ws) = skip_ws(text, i);
-            if !had_ws {
-                return Err(Error::RepeatError {
-                    message: format!("invalid @repeat syntax at byte {}", start),
-                });
+            let next_i = skip_ws(text, i);
+            if next_i == i {
+                return Err(repeat_error(source, "invalid @repeat syntax", start));
             }
             i = next_i;
             if i >= bytes.len() {
-                return Err(Error::RepeatError {
-                    message: format!("missing repeat target at byte {}", start),
-                });
+                return Err(repeat_error(source, "missing repeat target", start));
             }
             if bytes[i] == b'{' {
-                let (inner, next_i) = parse_section(text, i + 1, Some(b'}'))?;
+                let (inner, next_i) = parse_section(source, i + 1, Some(b'}'), consts)?;
                 for _ in 0..count {
-                    out.push_str(&inner);
+                    out.push_mapped(&inner);
                 }
                 i = next_i;
             } else {
                 let (ch, len) = next_char(text, i)?;
                 if ch.is_whitespace() {
-                    return Err(Error::RepeatError {
-                        message: format!("invalid repeat target at byte {}", i),
-                    });
+                    return Err(repeat_error(source, "invalid repeat target", i));
                 }
                 for _ in 0..count {
-                    out.push(ch);
+                    out.push_range(source, i..i + len);
                 }
                 i += len;
             }
             continue;
         }
-        let (ch, len) = next_char(text, i)?;
-        out.push(ch);
+        let (_, len) = next_char(text, i)?;
+        out.push_range(source, i..i + len);
         i += len;
     }
     if end_char.is_some() {
-        Err(Error::RepeatError {
-            message: "missing '}'".to_string(),
-        })
+        Err(repeat_error(source, "missing '}'", open))
     } else {
         Ok((out, i))
     }
 }
 
-fn skip_ws(text: &str, mut i: usize) -> (usize, bool) {
-    let bytes = text.as_bytes();
-    let mut consumed = false;
-    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
-        consumed = true;
-        i += 1;
-    }
-    (i, consumed)
+fn is_directive(text: &str, i: usize, directive: &str) -> bool {
+    text[i..].starts_with(directive)
+        && text.as_bytes().get(i + directive.len()).is_some_and(u8::is_ascii_whitespace)
 }
 
-fn parse_number(text: &str, start: usize) -> Result<(usize, usize), Error> {
-    let bytes = text.as_bytes();
-    let mut i = start;
-    let mut value: usize = 0;
-    let mut saw = false;
-    while i < bytes.len() {
-        let b = bytes[i];
-        if b.is_ascii_digit() {
-            saw = true;
-            let digit = (b - b'0') as usize;
-            value = value
-                .checked_mul(10)
-                .and_then(|v| v.checked_add(digit))
-                .ok_or_else(|| Error::RepeatError {
-                    message: "repeat count overflow".to_string(),
-                })?;
-            i += 1;
-        } else {
-            break;
-        }
-    }
-    if !saw {
-        return Err(Error::RepeatError {
-            message: format!("expected repeat count at byte {}", start),
-        });
+fn repeat_error(source: &Mapped, message: &str, pos: usize) -> Error {
+    Error::RepeatError {
+        message: message.to_string(),
     }
-    Ok((value, i))
+    .at(source, pos)
 }
 
 fn next_char(text: &str, i: usize) -> Result<(char, usize), Error> {
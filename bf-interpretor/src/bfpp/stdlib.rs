@@ -0,0 +1,17 @@
+pub const PREFIX: &str = "<std>";
+
+const FILES: &[(&str, &str)] = &[
+    ("memory.bfpp", include_str!("../../programs/lib/memory.bfpp")),
+    ("print.bfpp", include_str!("../../programs/lib/print.bfpp")),
+    ("copy.bfpp", include_str!("../../programs/lib/copy.bfpp")),
+    ("compare.bfpp", include_str!("../../programs/lib/compare.bfpp")),
+    ("divmod.bfpp", include_str!("../../programs/lib/divmod.bfpp")),
+    ("decimal.bfpp", include_str!("../../programs/lib/decimal.bfpp")),
+];
+
+pub fn lookup(name: &str) -> Option<&'static str> {
+    FILES
+        .iter()
+        .find(|(file, _)| *file == name)
+        .map(|(_, text)| *text)
+}
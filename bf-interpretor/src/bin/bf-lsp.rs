@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use bf::bfpp::{expr, lexer};
+use bf::json::{self, Json};
+use bf::{bfpp, brackets, compile, ir, parse};
+
+const SEVERITY_ERROR: u64 = 1;
+const SYNC_FULL: u64 = 1;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+struct Document {
+    path: PathBuf,
+    text: String,
+}
+
+impl Document {
+    fn is_bfpp(&self) -> bool {
+        self.path.extension().and_then(|e| e.to_str()) == Some("bfpp")
+    }
+
+    fn analysis_text(&self) -> String {
+        if !self.is_bfpp() {
+            return self.text.clone();
+        }
+        bfpp::driver::strip_comments(&self.text)
+            .split_inclusive('\n')
+            .map(|line| {
+                if line.trim_start().starts_with("#include") {
+                    if line.ends_with('\n') { "\n" } else { "" }
+                } else {
+                    line
+                }
+            })
+            .collect()
+    }
+
+    fn include_lines(&self) -> Vec<(usize, &str)> {
+        self.text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.trim_start().starts_with("#include"))
+            .collect()
+    }
+}
+
+fn main() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    if let Err(err) = serve(stdin.lock(), stdout.lock()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn serve<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> Result<(), String> {
+    let mut documents: HashMap<String, Document> = HashMap::new();
+    while let Some(message) = json::read_message(&mut reader)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                let error = Json::object(vec![
+                    ("code", Json::from(json::PARSE_ERROR)),
+                    ("message", Json::from(err)),
+                ]);
+                json::write_message(
+                    &mut writer,
+                    &Json::object(vec![
+                        ("jsonrpc", Json::from("2.0")),
+                        ("id", Json::Null),
+                        ("error", error),
+                    ]),
+                )?;
+                continue;
+            }
+        };
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let id = message.get("id").cloned();
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|d| d.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "shutdown" => Some(Json::Null),
+            "exit" => return Ok(()),
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|d| d.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("")
+                    .to_string();
+                documents.insert(
+                    uri.clone(),
+                    Document {
+                        path: uri_to_path(&uri),
+                        text,
+                    },
+                );
+                publish(&mut writer, &uri, documents.get(&uri))?;
+                None
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").and_then(Json::as_array);
+                let text = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c.get("text"))
+                    .and_then(Json::as_str);
+                if let (Some(doc), Some(text)) = (documents.get_mut(&uri), text) {
+                    doc.text = text.to_string();
+                }
+                publish(&mut writer, &uri, documents.get(&uri))?;
+                None
+            }
+            "textDocument/didSave" => {
+                publish(&mut writer, &uri, documents.get(&uri))?;
+                None
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish(&mut writer, &uri, None)?;
+                None
+            }
+            "textDocument/hover" => Some(
+                documents
+                    .get(&uri)
+                    .and_then(|doc| hover(doc, position_arg(&params)))
+                    .unwrap_or(Json::Null),
+            ),
+            "textDocument/definition" => Some(
+                documents
+                    .get(&uri)
+                    .and_then(|doc| definition(doc, position_arg(&params)))
+                    .unwrap_or(Json::Null),
+            ),
+            "textDocument/foldingRange" => Some(
+                documents
+                    .get(&uri)
+                    .map(|doc| Json::from(folding_ranges(doc)))
+                    .unwrap_or(Json::Null),
+            ),
+            _ => {
+                if let Some(id) = id.clone() {
+                    let error = Json::object(vec![
+                        ("code", Json::from(METHOD_NOT_FOUND)),
+                        ("message", Json::from(format!("unsupported method '{}'", method))),
+                    ]);
+                    json::write_message(
+                        &mut writer,
+                        &Json::object(vec![
+                            ("jsonrpc", Json::from("2.0")),
+                            ("id", id),
+                            ("error", error),
+                        ]),
+                    )?;
+                }
+                None
+            }
+        };
+
+        if let (Some(id), Some(result)) = (id, result) {
+            json::write_message(
+                &mut writer,
+                &Json::object(vec![
+                    ("jsonrpc", Json::from("2.0")),
+                    ("id", id),
+                    ("result", result),
+                ]),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                ("textDocumentSync", Json::from(SYNC_FULL)),
+                ("hoverProvider", Json::from(true)),
+                ("definitionProvider", Json::from(true)),
+                ("foldingRangeProvider", Json::from(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![("name", Json::from("bf-lsp"))]),
+        ),
+    ])
+}
+
+fn position_arg(params: &Json) -> (usize, usize) {
+    let position = params.get("position");
+    let field = |name| {
+        position
+            .and_then(|p| p.get(name))
+            .and_then(Json::as_u64)
+            .unwrap_or(0) as usize
+    };
+    (field("line"), field("character"))
+}
+
+fn publish<W: Write>(writer: &mut W, uri: &str, doc: Option<&Document>) -> Result<(), String> {
+    let diagnostics = doc.map(diagnostics).unwrap_or_default();
+    json::write_message(
+        writer,
+        &Json::object(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::object(vec![
+                    ("uri", Json::from(uri)),
+                    ("diagnostics", Json::from(diagnostics)),
+                ]),
+            ),
+        ]),
+    )
+}
+
+fn diagnostic(range: Json, message: String) -> Json {
+    Json::object(vec![
+        ("range", range),
+        ("severity", Json::from(SEVERITY_ERROR)),
+        ("source", Json::from("bf")),
+        ("message", Json::from(message)),
+    ])
+}
+
+fn bracket_diagnostic(text: &str) -> Option<Json> {
+    let ops = parse::filter_ops(text.as_bytes());
+    let offsets = parse::op_offsets(text.as_bytes());
+    let err = brackets::match_brackets(&ops).err()?;
+    let start = offsets[err.index];
+    Some(diagnostic(range(text, start, start + 1), err.to_string()))
+}
+
+fn diagnostics(doc: &Document) -> Vec<Json> {
+    if !doc.is_bfpp() {
+        return bracket_diagnostic(&doc.text).into_iter().collect();
+    }
+
+    let includes = doc.include_lines();
+    let options = bfpp::Options {
+        include_dirs: bfpp::include::env_search_path(),
+        ..bfpp::Options::default()
+    };
+    let mut out = Vec::new();
+    for (line, text) in &includes {
+        if let Err(err) = bfpp::include::resolve_includes_source(&doc.path, text, &options) {
+            out.push(diagnostic(line_range(*line, text), err.unlocated().to_string()));
+        }
+    }
+    if !out.is_empty() {
+        return out;
+    }
+
+    match bfpp::driver::preprocess_source(&doc.path, &doc.text, &options) {
+        Err(err) => {
+            let start = match err.span() {
+                Some(span) if span.path == doc.path => {
+                    line_col_to_offset(&doc.text, span.line - 1, span.col - 1)
+                }
+                _ => 0,
+            };
+            let message = err.unlocated().to_string();
+            out.push(diagnostic(range(&doc.text, start, start + 1), message));
+        }
+        Ok(expanded) => {
+            let ops = parse::filter_ops(expanded.as_bytes());
+            if let Err(err) = brackets::match_brackets(&ops) {
+                let located = bracket_diagnostic(&doc.analysis_text());
+                out.push(located.unwrap_or_else(|| {
+                    diagnostic(range(&doc.text, 0, 0), format!("{} in expanded output", err))
+                }));
+            }
+        }
+    }
+    out
+}
+
+fn loop_spans(text: &str) -> Vec<(usize, usize)> {
+    let ops = parse::filter_ops(text.as_bytes());
+    let offsets = parse::op_offsets(text.as_bytes());
+    let jumps = match brackets::match_brackets(&ops) {
+        Ok(jumps) => jumps,
+        Err(_) => return Vec::new(),
+    };
+    ops.iter()
+        .enumerate()
+        .filter(|(_, &op)| op == b'[')
+        .map(|(idx, _)| (offsets[idx], offsets[jumps[idx]]))
+        .collect()
+}
+
+fn hover(doc: &Document, (line, character): (usize, usize)) -> Option<Json> {
+    let text = doc.analysis_text();
+    let offset = position_to_offset(&text, line, character);
+    let (start, end) = loop_spans(&text)
+        .into_iter()
+        .filter(|&(open, close)| open <= offset && offset <= close)
+        .max_by_key(|&(open, _)| open)?;
+    let source = &text[start..=end];
+    let source = if doc.is_bfpp() {
+        bfpp::repeat::expand_repeats(source).ok()?
+    } else {
+        source.to_string()
+    };
+    let program = compile(source.as_bytes(), false).ok()?;
+    let mut dump = Vec::new();
+    ir::dump_ir(&program.ir, &mut dump).ok()?;
+    let value = format!("```\n{}```", String::from_utf8_lossy(&dump));
+    Some(Json::object(vec![
+        (
+            "contents",
+            Json::object(vec![
+                ("kind", Json::from("markdown")),
+                ("value", Json::from(value)),
+            ]),
+        ),
+        ("range", range(&text, start, end + 1)),
+    ]))
+}
+
+fn definition(doc: &Document, (line, _): (usize, usize)) -> Option<Json> {
+    let text = doc.text.lines().nth(line)?;
+    let trimmed = text.trim_start();
+    if !trimmed.starts_with("#include") {
+        return None;
+    }
+    let target = match bfpp::include::parse_include(trimmed, &doc.path).ok()? {
+        bfpp::include::Include::Local(include) => {
+            doc.path.parent().unwrap_or_else(|| Path::new(".")).join(include)
+        }
+        bfpp::include::Include::Library(name) => {
+            let search = bfpp::include::env_search_path();
+            bfpp::include::find_library(&name, &search)?
+        }
+    };
+    if !target.is_file() {
+        return None;
+    }
+    let target = std::fs::canonicalize(&target).unwrap_or(target);
+    Some(Json::object(vec![
+        ("uri", Json::from(path_to_uri(&target))),
+        ("range", range("", 0, 0)),
+    ]))
+}
+
+fn folding_ranges(doc: &Document) -> Vec<Json> {
+    let text = doc.analysis_text();
+    let mut spans = loop_spans(&text);
+    if doc.is_bfpp() {
+        spans.extend(repeat_spans(&text));
+    }
+    spans.sort();
+    spans
+        .into_iter()
+        .map(|(start, end)| {
+            let (start_line, _) = parse::line_col(text.as_bytes(), start);
+            let (end_line, _) = parse::line_col(text.as_bytes(), end);
+            (start_line - 1, end_line - 1)
+        })
+        .filter(|(start, end)| end > start)
+        .map(|(start, end)| {
+            Json::object(vec![
+                ("startLine", Json::from(start)),
+                ("endLine", Json::from(end)),
+            ])
+        })
+        .collect()
+}
+
+fn repeat_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut search = 0;
+    while let Some(found) = text[search..].find("@repeat") {
+        let start = search + found;
+        search = start + "@repeat".len();
+        let count = lexer::skip_ws(text, search);
+        let Some(end) = expr::extent(text, count) else {
+            continue;
+        };
+        let open = lexer::skip_ws(text, end);
+        if text.as_bytes().get(open) != Some(&b'{') {
+            continue;
+        }
+        if let Some(close) = lexer::matching(text, open, b'{', b'}') {
+            spans.push((start, close));
+        }
+    }
+    spans
+}
+
+fn line_col_to_offset(text: &str, line: usize, col: usize) -> usize {
+    let mut offset = 0;
+    for (idx, part) in text.split_inclusive('\n').enumerate() {
+        if idx == line {
+            return offset + col.min(part.len());
+        }
+        offset += part.len();
+    }
+    text.len()
+}
+
+fn position_to_offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = line_col_to_offset(text, line, 0);
+    let mut units = 0;
+    for (idx, ch) in text[line_start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return line_start + idx;
+        }
+        units += ch.len_utf16();
+    }
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Json {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = text[..line_start].matches('\n').count();
+    let character: usize = text[line_start..offset].chars().map(char::len_utf16).sum();
+    Json::object(vec![
+        ("line", Json::from(line)),
+        ("character", Json::from(character)),
+    ])
+}
+
+fn range(text: &str, start: usize, end: usize) -> Json {
+    Json::object(vec![
+        ("start", position(text, start)),
+        ("end", position(text, end)),
+    ])
+}
+
+fn line_range(line: usize, text: &str) -> Json {
+    let end = text.chars().map(char::len_utf16).sum::<usize>();
+    let at = |character: usize| {
+        Json::object(vec![
+            ("line", Json::from(line)),
+            ("character", Json::from(character)),
+        ])
+    };
+    Json::object(vec![("start", at(0)), ("end", at(end))])
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let raw = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |at: usize| bytes.get(at).and_then(|&b| (b as char).to_digit(16));
+        if bytes[i] == b'%' {
+            if let (Some(high), Some(low)) = (hex(i + 1), hex(i + 2)) {
+                out.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    PathBuf::from(String::from_utf8_lossy(&out).into_owned())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut out = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'/' | b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
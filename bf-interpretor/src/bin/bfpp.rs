@@ -1,9 +1,11 @@
+use bf::bfpp;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-#[path = "../bfpp/mod.rs"]
-mod bfpp;
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 fn main() {
     if let Err(err) = run() {
@@ -12,30 +14,186 @@ fn main() {
     }
 }
 
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+    map: Option<PathBuf>,
+    deps: Option<PathBuf>,
+    options: bfpp::Options,
+}
+
 fn run() -> Result<(), bfpp::Error> {
+    let mut input = None;
+    let mut output = None;
+    let mut map = None;
+    let mut deps = None;
+    let mut watch = false;
+    let mut options = bfpp::Options::default();
     let mut args = env::args().skip(1);
-    let input = match args.next() {
-        Some(value) => value,
-        None => return Err(bfpp::Error::Usage),
-    };
-    if args.next().as_deref() != Some("-o") {
-        return Err(bfpp::Error::Usage);
+    while let Some(arg) = args.next() {
+        if arg == "-I" {
+            let dir = args.next().ok_or(bfpp::Error::Usage)?;
+            options.include_dirs.push(PathBuf::from(dir));
+            continue;
+        }
+        if arg == "-D" {
+            let define = args.next().ok_or(bfpp::Error::Usage)?;
+            options.defines.push(parse_define(&define)?);
+            continue;
+        }
+        if let Some(define) = arg.strip_prefix("-D") {
+            options.defines.push(parse_define(define)?);
+            continue;
+        }
+        if arg == "-v" || arg == "--verbose" {
+            options.verbose = true;
+            continue;
+        }
+        if arg == "--watch" {
+            watch = true;
+            continue;
+        }
+        if arg == "--keep-comments" {
+            options.keep_comments = true;
+            continue;
+        }
+        if arg == "--pretty" {
+            options.pretty = true;
+            continue;
+        }
+        if let Some(dir) = arg.strip_prefix("-I") {
+            options.include_dirs.push(PathBuf::from(dir));
+            continue;
+        }
+        let slot = match arg.as_str() {
+            "-o" => &mut output,
+            "-m" => &mut map,
+            "-M" => &mut deps,
+            _ if arg.starts_with('-') || input.is_some() => return Err(bfpp::Error::Usage),
+            _ => {
+                input = Some(arg);
+                continue;
+            }
+        };
+        if slot.is_some() {
+            return Err(bfpp::Error::Usage);
+        }
+        *slot = Some(args.next().ok_or(bfpp::Error::Usage)?);
     }
-    let output = match args.next() {
-        Some(value) => value,
-        None => return Err(bfpp::Error::Usage),
+    let (input, output) = match (input, output) {
+        (Some(input), Some(output)) => (input, output),
+        _ => return Err(bfpp::Error::Usage),
     };
-    if args.next().is_some() {
-        return Err(bfpp::Error::Usage);
+
+    options.include_dirs.extend(bfpp::include::env_search_path());
+    let job = Job {
+        input: PathBuf::from(input),
+        output: PathBuf::from(output),
+        map: map.map(PathBuf::from),
+        deps: deps.map(PathBuf::from),
+        options,
+    };
+
+    if !watch {
+        return build(&job).1;
     }
 
-    let input_path = PathBuf::from(input);
-    let output_path = PathBuf::from(output);
+    let mut watched = vec![job.input.clone()];
+    loop {
+        let (found, result) = build(&job);
+        match result {
+            Ok(()) => eprintln!("bfpp: wrote {}", job.output.display()),
+            Err(err) => eprintln!("{}", err),
+        }
+        if !found.is_empty() {
+            watched = found;
+        }
+        let stamps = snapshot(&watched);
+        while snapshot(&watched) == stamps {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn build(job: &Job) -> (Vec<PathBuf>, Result<(), bfpp::Error>) {
+    let resolved = match bfpp::include::resolve_includes(&job.input, &job.options) {
+        Ok(resolved) => resolved,
+        Err(err) => return (Vec::new(), Err(err)),
+    };
+    let deps = bfpp::include::dependencies(&resolved);
+    let result = bfpp::driver::expand(&resolved, &job.options)
+        .and_then(|processed| write_outputs(job, &processed, &deps));
+    (deps, result)
+}
+
+fn write_outputs(
+    job: &Job,
+    processed: &bfpp::mapped::Mapped,
+    deps: &[PathBuf],
+) -> Result<(), bfpp::Error> {
+    write_file(&job.output, &processed.text)?;
 
-    let processed = bfpp::preprocess(&input_path)?;
+    if let Some(map_path) = &job.map {
+        write_file(map_path, &processed.source_map().to_string())?;
+    }
 
-    std::fs::write(&output_path, processed)
-        .map_err(|e| bfpp::Error::WriteFailed { path: output_path, source: e })?;
+    if let Some(deps_path) = &job.deps {
+        let mut targets = vec![&job.output];
+        targets.extend(&job.map);
+        write_file(deps_path, &make_rule(&targets, deps))?;
+    }
 
     Ok(())
 }
+
+fn write_file(path: &Path, text: &str) -> Result<(), bfpp::Error> {
+    std::fs::write(path, text)
+        .map_err(|e| bfpp::Error::WriteFailed { path: path.to_path_buf(), source: e })
+}
+
+fn make_rule(targets: &[&PathBuf], deps: &[PathBuf]) -> String {
+    let targets: Vec<String> = targets.iter().map(|path| make_escape(path)).collect();
+    let mut rule = format!("{}:", targets.join(" "));
+    for dep in deps {
+        rule.push_str(" \\\n  ");
+        rule.push_str(&make_escape(dep));
+    }
+    rule.push('\n');
+    for dep in deps.iter().skip(1) {
+        rule.push_str(&format!("\n{}:\n", make_escape(dep)));
+    }
+    rule
+}
+
+fn make_escape(path: &Path) -> String {
+    let mut out = String::new();
+    for ch in path.display().to_string().chars() {
+        match ch {
+            ' ' | '#' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            '$' => out.push_str("$$"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+fn snapshot(paths: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+    paths
+        .iter()
+        .map(|path| {
+            let meta = std::fs::metadata(path).ok()?;
+            Some((meta.modified().ok()?, meta.len()))
+        })
+        .collect()
+}
+
+fn parse_define(define: &str) -> Result<(String, String), bfpp::Error> {
+    let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+    if !bfpp::include::is_identifier(name) {
+        return Err(bfpp::Error::Usage);
+    }
+    Ok((name.to_string(), value.to_string()))
+}
@@ -1,14 +1,38 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BracketError {
+    pub index: usize,
+    pub op: u8,
+}
+
+impl fmt::Display for BracketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "syntax error: unmatched '{}' at {}",
+            self.op as char, self.index
+        )
+    }
+}
+
 pub fn build_jumps(ops: &[u8]) -> Result<Vec<usize>, String> {
+    match_brackets(ops).map_err(|e| e.to_string())
+}
+
+pub fn match_brackets(ops: &[u8]) -> Result<Vec<usize>, BracketError> {
     let mut jumps = vec![usize::MAX; ops.len()];
     let mut stack = Vec::new();
 
     for (idx, &op) in ops.iter().enumerate() {
         match op {
-            b'[' => stack.push(idx),
-            b']' => {
-                let open = stack
-                    .pop()
-                    .ok_or_else(|| format!("syntax error: unmatched ']' at {}", idx))?;
+            b'[' | b'(' => stack.push(idx),
+            b']' | b')' => {
+                let opener = if op == b']' { b'[' } else { b'(' };
+                let open = match stack.pop() {
+                    Some(open) if ops[open] == opener => open,
+                    _ => return Err(BracketError { index: idx, op }),
+                };
                 jumps[open] = idx;
                 jumps[idx] = open;
             }
@@ -17,7 +41,10 @@ pub fn build_jumps(ops: &[u8]) -> Result<Vec<usize>, String> {
     }
 
     if let Some(&open) = stack.last() {
-        return Err(format!("syntax error: unmatched '[' at {}", open));
+        return Err(BracketError {
+            index: open,
+            op: ops[open],
+        });
     }
 
     Ok(jumps)
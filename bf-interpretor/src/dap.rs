@@ -0,0 +1,501 @@
+use std::io::{BufRead, Cursor, Write};
+use std::path::Path;
+
+use crate::io::{Input, Output};
+use crate::ir::Instr;
+use crate::json::{self, Json};
+use crate::srcmap::{Location, SourceMap};
+use crate::vm::{Halt, Vm};
+use crate::{bfpp, compile, parse, Program};
+
+const THREAD_ID: u64 = 1;
+const TAPE_REFERENCE: u64 = 1;
+const DEFAULT_TAPE_SIZE: usize = 30_000;
+const TAPE_WINDOW: usize = 8;
+
+enum Resume {
+    Continue,
+    StepIn,
+    StepOver,
+}
+
+struct Debuggee {
+    path: String,
+    bytes: Vec<u8>,
+    source_map: Option<SourceMap>,
+    program: Program,
+    vm: Vm,
+    input: Input<Cursor<Vec<u8>>>,
+    output: Output<Vec<u8>>,
+    max_steps: Option<u64>,
+    stop_on_entry: bool,
+}
+
+impl Debuggee {
+    fn location(&self, ip: usize) -> Location {
+        let offset = match self.program.positions.get(ip) {
+            Some(offset) => *offset,
+            None => self.bytes.len(),
+        };
+        if let Some(location) = self.source_map.as_ref().and_then(|map| map.lookup(offset)) {
+            return location;
+        }
+        let (line, col) = parse::line_col(&self.bytes, offset);
+        Location { file: self.path.clone(), line, col }
+    }
+
+    fn ip_for_line(&self, file: &str, line: usize) -> Option<usize> {
+        (0..self.program.ir.len())
+            .map(|ip| (self.location(ip), ip))
+            .filter(|(location, _)| location.line >= line && same_file(&location.file, file))
+            .min_by_key(|(location, ip)| (location.line, *ip))
+            .map(|(_, ip)| ip)
+    }
+
+    fn step_over(&mut self) -> Result<Halt, String> {
+        let ir = &self.program.ir;
+        let depth = self.vm.call_depth();
+        let after = match ir.get(self.vm.ip()) {
+            Some(Instr::Jz(close)) => *close + 1,
+            Some(Instr::Call) => self.vm.ip() + 1,
+            _ => return self.step_in(),
+        };
+        loop {
+            let halt = self.step_in()?;
+            if halt != Halt::Stepped || (self.vm.ip() == after && self.vm.call_depth() <= depth) {
+                return Ok(halt);
+            }
+            if self.vm.is_breakpoint(self.vm.ip()) {
+                return Ok(Halt::Breakpoint(self.vm.ip()));
+            }
+        }
+    }
+
+    fn step_in(&mut self) -> Result<Halt, String> {
+        self.vm.step::<_, _, Vec<u8>>(
+            &self.program.ir,
+            &mut self.input,
+            &mut self.output,
+            None,
+            self.max_steps,
+        )
+    }
+
+    fn resume(&mut self, mode: Resume) -> Result<Halt, String> {
+        match mode {
+            Resume::Continue => self.vm.resume::<_, _, Vec<u8>>(
+                &self.program.ir,
+                &mut self.input,
+                &mut self.output,
+                None,
+                self.max_steps,
+            ),
+            Resume::StepIn => self.step_in(),
+            Resume::StepOver => self.step_over(),
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        let _ = self.output.flush();
+        std::mem::take(self.output.get_mut())
+    }
+}
+
+struct Session<W: Write> {
+    writer: W,
+    seq: u64,
+    debuggee: Option<Debuggee>,
+    breakpoint_lines: Vec<(String, Vec<usize>)>,
+    configured: bool,
+    running: bool,
+}
+
+pub fn serve<R: BufRead, W: Write>(mut reader: R, writer: W) -> Result<(), String> {
+    let mut session = Session {
+        writer,
+        seq: 0,
+        debuggee: None,
+        breakpoint_lines: Vec::new(),
+        configured: false,
+        running: false,
+    };
+    while let Some(message) = json::read_message(&mut reader)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                session.output("console", format!("{}\n", err))?;
+                continue;
+            }
+        };
+        if !session.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl<W: Write> Session<W> {
+    fn send(&mut self, mut fields: Vec<(&str, Json)>) -> Result<(), String> {
+        self.seq += 1;
+        fields.insert(0, ("seq", Json::from(self.seq)));
+        json::write_message(&mut self.writer, &Json::object(fields))
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> Result<(), String> {
+        let request_seq = request.get("seq").cloned().unwrap_or(Json::Null);
+        let command = request.get("command").cloned().unwrap_or(Json::Null);
+        self.send(vec![
+            ("type", Json::from("response")),
+            ("request_seq", request_seq),
+            ("success", Json::from(true)),
+            ("command", command),
+            ("body", body),
+        ])
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) -> Result<(), String> {
+        let request_seq = request.get("seq").cloned().unwrap_or(Json::Null);
+        let command = request.get("command").cloned().unwrap_or(Json::Null);
+        self.send(vec![
+            ("type", Json::from("response")),
+            ("request_seq", request_seq),
+            ("success", Json::from(false)),
+            ("command", command),
+            ("message", Json::from(message)),
+        ])
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<(), String> {
+        self.send(vec![
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ])
+    }
+
+    fn handle(&mut self, request: &Json) -> Result<bool, String> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let args = request.get("arguments").cloned().unwrap_or(Json::Null);
+        match command {
+            "initialize" => {
+                let body = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", Json::from(true)),
+                    ("supportsTerminateRequest", Json::from(true)),
+                ]);
+                self.respond(request, body)?;
+                self.event("initialized", Json::object(vec![]))?;
+            }
+            "launch" => match load(&args) {
+                Ok(debuggee) => {
+                    self.debuggee = Some(debuggee);
+                    self.apply_breakpoints(None);
+                    self.respond(request, Json::object(vec![]))?;
+                    self.start()?;
+                }
+                Err(err) => self.fail(request, &err)?,
+            },
+            "setBreakpoints" => {
+                let source = args
+                    .get("source")
+                    .and_then(|source| source.get("path"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("")
+                    .to_string();
+                let lines = args
+                    .get("breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or(&[])
+                    .iter()
+                    .filter_map(|bp| bp.get("line").and_then(Json::as_u64))
+                    .map(|line| line as usize)
+                    .collect();
+                self.breakpoint_lines.retain(|(path, _)| *path != source);
+                self.breakpoint_lines.push((source.clone(), lines));
+                let resolved = self.apply_breakpoints(Some(&source));
+                let breakpoints = resolved
+                    .into_iter()
+                    .map(|(requested, actual)| match actual {
+                        Some(line) => Json::object(vec![
+                            ("verified", Json::from(true)),
+                            ("line", Json::from(line)),
+                        ]),
+                        None => Json::object(vec![
+                            ("verified", Json::from(false)),
+                            ("line", Json::from(requested)),
+                        ]),
+                    })
+                    .collect::<Vec<_>>();
+                self.respond(
+                    request,
+                    Json::object(vec![("breakpoints", Json::from(breakpoints))]),
+                )?;
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(request, Json::object(vec![]))?;
+                self.start()?;
+            }
+            "threads" => {
+                let thread = Json::object(vec![
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("main")),
+                ]);
+                self.respond(
+                    request,
+                    Json::object(vec![("threads", Json::from(vec![thread]))]),
+                )?;
+            }
+            "stackTrace" => {
+                let frames = match &self.debuggee {
+                    Some(debuggee) => {
+                        let location = debuggee.location(debuggee.vm.ip());
+                        let name = Path::new(&location.file)
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| location.file.clone());
+                        vec![Json::object(vec![
+                            ("id", Json::from(1u64)),
+                            ("name", Json::from("main")),
+                            (
+                                "source",
+                                Json::object(vec![
+                                    ("name", Json::from(name)),
+                                    ("path", Json::from(location.file.as_str())),
+                                ]),
+                            ),
+                            ("line", Json::from(location.line)),
+                            ("column", Json::from(location.col)),
+                        ])]
+                    }
+                    None => Vec::new(),
+                };
+                let total = frames.len();
+                self.respond(
+                    request,
+                    Json::object(vec![
+                        ("stackFrames", Json::from(frames)),
+                        ("totalFrames", Json::from(total)),
+                    ]),
+                )?;
+            }
+            "scopes" => {
+                let scope = Json::object(vec![
+                    ("name", Json::from("Tape")),
+                    ("variablesReference", Json::from(TAPE_REFERENCE)),
+                    ("expensive", Json::from(false)),
+                ]);
+                self.respond(
+                    request,
+                    Json::object(vec![("scopes", Json::from(vec![scope]))]),
+                )?;
+            }
+            "variables" => {
+                let variables = match &self.debuggee {
+                    Some(debuggee) => tape_variables(&debuggee.vm),
+                    None => Vec::new(),
+                };
+                self.respond(
+                    request,
+                    Json::object(vec![("variables", Json::from(variables))]),
+                )?;
+            }
+            "continue" => {
+                self.respond(
+                    request,
+                    Json::object(vec![("allThreadsContinued", Json::from(true))]),
+                )?;
+                self.resume(Resume::Continue)?;
+            }
+            "next" => {
+                self.respond(request, Json::object(vec![]))?;
+                self.resume(Resume::StepOver)?;
+            }
+            "stepIn" => {
+                self.respond(request, Json::object(vec![]))?;
+                self.resume(Resume::StepIn)?;
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Json::object(vec![]))?;
+                return Ok(false);
+            }
+            _ => {
+                self.fail(request, &format!("unsupported request '{}'", command))?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn apply_breakpoints(&mut self, source: Option<&str>) -> Vec<(usize, Option<usize>)> {
+        let mut resolved = Vec::new();
+        let mut ips = Vec::new();
+        for (path, lines) in &self.breakpoint_lines {
+            for &line in lines {
+                let ip = self.debuggee.as_ref().and_then(|debuggee| {
+                    let file = if path.is_empty() { &debuggee.path } else { path };
+                    let ip = debuggee.ip_for_line(file, line)?;
+                    Some((ip, debuggee.location(ip).line))
+                });
+                if let Some((ip, _)) = ip {
+                    ips.push(ip);
+                }
+                if source == Some(path.as_str()) {
+                    resolved.push((line, ip.map(|(_, actual)| actual)));
+                }
+            }
+        }
+        if let Some(debuggee) = self.debuggee.as_mut() {
+            debuggee.vm.set_breakpoints(ips);
+        }
+        resolved
+    }
+
+    fn start(&mut self) -> Result<(), String> {
+        if self.running || !self.configured {
+            return Ok(());
+        }
+        let (stop_on_entry, at_breakpoint) = match &self.debuggee {
+            Some(debuggee) => (debuggee.stop_on_entry, debuggee.vm.is_breakpoint(0)),
+            None => return Ok(()),
+        };
+        self.running = true;
+        if stop_on_entry {
+            self.stopped("entry")
+        } else if at_breakpoint {
+            self.stopped("breakpoint")
+        } else {
+            self.resume(Resume::Continue)
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) -> Result<(), String> {
+        self.event(
+            "stopped",
+            Json::object(vec![
+                ("reason", Json::from(reason)),
+                ("threadId", Json::from(THREAD_ID)),
+                ("allThreadsStopped", Json::from(true)),
+            ]),
+        )
+    }
+
+    fn resume(&mut self, mode: Resume) -> Result<(), String> {
+        let debuggee = match self.debuggee.as_mut() {
+            Some(debuggee) => debuggee,
+            None => return Ok(()),
+        };
+        let result = debuggee.resume(mode);
+        let bytes = debuggee.take_output();
+        if !bytes.is_empty() {
+            let text = String::from_utf8_lossy(&bytes).into_owned();
+            self.output("stdout", text)?;
+        }
+        match result {
+            Ok(Halt::Finished) => self.exit(0),
+            Ok(Halt::Stepped) => self.stopped("step"),
+            Ok(Halt::Breakpoint(_)) => self.stopped("breakpoint"),
+            Ok(Halt::Watch(_)) => self.stopped("data breakpoint"),
+            Err(err) => {
+                self.output("stderr", format!("{}\n", err))?;
+                self.exit(1)
+            }
+        }
+    }
+
+    fn output(&mut self, category: &str, text: String) -> Result<(), String> {
+        self.event(
+            "output",
+            Json::object(vec![
+                ("category", Json::from(category)),
+                ("output", Json::from(text)),
+            ]),
+        )
+    }
+
+    fn exit(&mut self, code: i64) -> Result<(), String> {
+        self.event("exited", Json::object(vec![("exitCode", Json::from(code))]))?;
+        self.event("terminated", Json::object(vec![]))
+    }
+}
+
+fn load(args: &Json) -> Result<Debuggee, String> {
+    let path = args
+        .get("program")
+        .and_then(Json::as_str)
+        .ok_or_else(|| "launch: missing 'program'".to_string())?
+        .to_string();
+    let no_opt = args.get("noOpt").and_then(Json::as_bool).unwrap_or(false);
+    let stop_on_entry = args
+        .get("stopOnEntry")
+        .and_then(Json::as_bool)
+        .unwrap_or(false);
+    let tape_size = args
+        .get("tape")
+        .and_then(Json::as_u64)
+        .map(|n| n as usize)
+        .unwrap_or(DEFAULT_TAPE_SIZE);
+    let max_steps = args.get("maxSteps").and_then(Json::as_u64);
+    let stdin = args
+        .get("input")
+        .and_then(Json::as_str)
+        .unwrap_or("")
+        .as_bytes()
+        .to_vec();
+
+    let (bytes, source_map) = if path.ends_with(".bfpp") {
+        let options = bfpp::Options {
+            include_dirs: bfpp::include::env_search_path(),
+            ..bfpp::Options::default()
+        };
+        let processed = bfpp::driver::preprocess_mapped(Path::new(&path), &options)
+            .map_err(|e| e.to_string())?;
+        let source_map = processed.source_map();
+        (processed.text.into_bytes(), Some(source_map))
+    } else {
+        let bytes =
+            std::fs::read(&path).map_err(|e| format!("failed to read '{}': {}", path, e))?;
+        (bytes, None)
+    };
+    let program = compile(&bytes, no_opt)?;
+    let vm = Vm::with_capacity(tape_size)?;
+    Ok(Debuggee {
+        path,
+        bytes,
+        source_map,
+        program,
+        vm,
+        input: Input::new(Cursor::new(stdin)),
+        output: Output::new(Vec::new()),
+        max_steps,
+        stop_on_entry,
+    })
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn tape_variables(vm: &Vm) -> Vec<Json> {
+    let tape = vm.tape();
+    let pointer = vm.pointer();
+    let start = pointer.saturating_sub(TAPE_WINDOW);
+    let end = (pointer + TAPE_WINDOW).min(tape.len().saturating_sub(1));
+    let mut variables = vec![Json::object(vec![
+        ("name", Json::from("ptr")),
+        ("value", Json::from(pointer.to_string())),
+        ("variablesReference", Json::from(0u64)),
+    ])];
+    for (idx, value) in tape.iter().enumerate().take(end + 1).skip(start) {
+        variables.push(Json::object(vec![
+            ("name", Json::from(format!("[{}]", idx))),
+            ("value", Json::from(value.to_string())),
+            ("variablesReference", Json::from(0u64)),
+        ]));
+    }
+    variables
+}
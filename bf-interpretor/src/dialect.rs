@@ -0,0 +1,246 @@
+use crate::parse;
+
+const OPS: [u8; 8] = *b"><+-.,[]";
+const WORDS_PER_LINE: usize = 8;
+const CHARS_PER_LINE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    tokens: Vec<String>,
+    extensions: Vec<u8>,
+}
+
+const BUILTINS: &[(&str, [&str; 8])] = &[
+    ("brainfuck", [">", "<", "+", "-", ".", ",", "[", "]"]),
+    (
+        "ook",
+        [
+            "Ook. Ook?",
+            "Ook? Ook.",
+            "Ook. Ook.",
+            "Ook! Ook!",
+            "Ook! Ook.",
+            "Ook. Ook!",
+            "Ook! Ook?",
+            "Ook? Ook!",
+        ],
+    ),
+    (
+        "blub",
+        [
+            "Blub. Blub?",
+            "Blub? Blub.",
+            "Blub. Blub.",
+            "Blub! Blub!",
+            "Blub! Blub.",
+            "Blub. Blub!",
+            "Blub! Blub?",
+            "Blub? Blub!",
+        ],
+    ),
+    ("reversefuck", ["<", ">", "-", "+", ",", ".", "]", "["]),
+    ("emoji", ["👉", "👈", "👍", "👎", "📢", "🎤", "🔁", "🔚"]),
+];
+
+const EXTENSIONS: &[(&str, &[u8])] = &[
+    ("pbrain", b"():"),
+    ("brainfork", b"Y"),
+    ("extended1", b"@$!}{~^&|"),
+    ("2d", b"^v"),
+    ("framebuffer", b"#"),
+];
+
+impl Dialect {
+    pub fn brainfuck() -> Dialect {
+        Dialect::builtin("brainfuck").unwrap()
+    }
+
+    pub fn builtin(name: &str) -> Option<Dialect> {
+        let name = if name == "bf" { "brainfuck" } else { name };
+        if EXTENSIONS.iter().any(|(extended, _)| extended.eq_ignore_ascii_case(name))
+            || name.contains('+')
+        {
+            return Dialect::extended(name);
+        }
+        BUILTINS
+            .iter()
+            .find(|(builtin, _)| builtin.eq_ignore_ascii_case(name))
+            .map(|(_, tokens)| Dialect {
+                tokens: tokens.iter().map(|token| token.to_string()).collect(),
+                extensions: Vec::new(),
+            })
+    }
+
+    fn extended(names: &str) -> Option<Dialect> {
+        let mut extensions: Vec<u8> = Vec::new();
+        for name in names.split('+') {
+            let (_, added) =
+                EXTENSIONS.iter().find(|(extended, _)| extended.eq_ignore_ascii_case(name))?;
+            if added.iter().any(|op| extensions.contains(op)) {
+                return None;
+            }
+            extensions.extend_from_slice(added);
+        }
+        extensions.sort_unstable();
+        Some(Dialect {
+            extensions,
+            ..Dialect::brainfuck()
+        })
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        BUILTINS
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(EXTENSIONS.iter().map(|(name, _)| *name))
+            .collect()
+    }
+
+    pub fn resolve(spec: &str) -> Result<Dialect, String> {
+        match Dialect::builtin(spec) {
+            Some(dialect) => Ok(dialect),
+            None if std::path::Path::new(spec).is_file() => Dialect::load(spec),
+            None => Err(format!(
+                "unknown dialect '{}' (expected {}, extensions joined with '+' such as \
+                 2d+framebuffer, or a token table file)",
+                spec,
+                Dialect::names().join(", ")
+            )),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Dialect, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read '{}': {}", path, e))?;
+        Dialect::parse_table(&text).map_err(|e| format!("{} in '{}'", e, path))
+    }
+
+    pub fn parse_table(text: &str) -> Result<Dialect, String> {
+        let mut tokens: Vec<Option<String>> = vec![None; OPS.len()];
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error =
+                |message: &str| format!("dialect table error: {} at line {}", message, idx + 1);
+            let (op, token) = line.split_at(line.chars().next().map_or(0, char::len_utf8));
+            let slot = OPS
+                .iter()
+                .position(|&b| op.as_bytes() == [b])
+                .ok_or_else(|| error("expected one of the eight BF commands"))?;
+            let token = token.split_whitespace().collect::<Vec<_>>().join(" ");
+            if token.is_empty() {
+                return Err(error("missing token"));
+            }
+            if tokens[slot].is_some() {
+                return Err(error("command defined twice"));
+            }
+            if tokens.iter().flatten().any(|other| *other == token) {
+                return Err(error("token used for two commands"));
+            }
+            tokens[slot] = Some(token);
+        }
+        let missing: String = OPS
+            .iter()
+            .zip(&tokens)
+            .filter(|(_, token)| token.is_none())
+            .map(|(&op, _)| op as char)
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("dialect table error: no token for '{}'", missing));
+        }
+        Ok(Dialect {
+            tokens: tokens.into_iter().flatten().collect(),
+            extensions: Vec::new(),
+        })
+    }
+
+    pub fn is_brainfuck(&self) -> bool {
+        *self == Dialect::brainfuck()
+    }
+
+    pub fn supports(&self, op: u8) -> bool {
+        OPS.contains(&op) || self.extensions.contains(&op)
+    }
+
+    pub fn tokenize(&self, bytes: &[u8]) -> (Vec<u8>, Vec<usize>) {
+        if self.tokens == Dialect::brainfuck().tokens {
+            return (
+                parse::filter_ops_with(bytes, &self.extensions),
+                parse::op_offsets_with(bytes, &self.extensions),
+            );
+        }
+        let mut ops = Vec::new();
+        let mut offsets = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            let found = self
+                .tokens
+                .iter()
+                .enumerate()
+                .filter_map(|(slot, token)| Some((slot, match_token(bytes, i, token)?)))
+                .max_by_key(|&(_, len)| len);
+            match found {
+                Some((slot, len)) => {
+                    ops.push(OPS[slot]);
+                    offsets.push(i);
+                    i += len;
+                }
+                None => i += 1,
+            }
+        }
+        (ops, offsets)
+    }
+
+    pub fn render(&self, ops: &[u8]) -> String {
+        let words = self.tokens.iter().any(|token| token.chars().count() > 1);
+        let (separator, per_line) = if words {
+            (" ", WORDS_PER_LINE)
+        } else {
+            ("", CHARS_PER_LINE)
+        };
+        let mut out = String::new();
+        for (idx, op) in ops.iter().enumerate() {
+            let token = match OPS.iter().position(|b| b == op) {
+                Some(slot) => self.tokens[slot].clone(),
+                None if self.extensions.contains(op) => (*op as char).to_string(),
+                None => continue,
+            };
+            if idx > 0 {
+                out.push_str(if idx % per_line == 0 { "\n" } else { separator });
+            }
+            out.push_str(&token);
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+}
+
+pub fn translate(bytes: &[u8], from: &Dialect, to: &Dialect) -> Result<String, String> {
+    let (ops, _) = from.tokenize(bytes);
+    if let Some(op) = ops.iter().find(|&&op| !to.supports(op)) {
+        return Err(format!("target dialect has no token for '{}'", *op as char));
+    }
+    Ok(to.render(&ops))
+}
+
+fn match_token(bytes: &[u8], start: usize, token: &str) -> Option<usize> {
+    let mut i = start;
+    for (idx, word) in token.split(' ').enumerate() {
+        if idx > 0 {
+            let ws = bytes[i..].iter().take_while(|b| b.is_ascii_whitespace()).count();
+            if ws == 0 {
+                return None;
+            }
+            i += ws;
+        }
+        if !bytes[i..].starts_with(word.as_bytes()) {
+            return None;
+        }
+        i += word.len();
+    }
+    Some(i - start)
+}
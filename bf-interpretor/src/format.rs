@@ -0,0 +1,150 @@
+use crate::{brackets, parse};
+
+const INDENT: &str = "    ";
+
+struct Layout {
+    out: String,
+    depth: usize,
+    line: String,
+    line_depth: usize,
+    code_open: bool,
+}
+
+impl Layout {
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            for _ in 0..self.line_depth {
+                self.out.push_str(INDENT);
+            }
+            self.out.push_str(&self.line);
+            self.out.push('\n');
+            self.line.clear();
+        }
+        self.code_open = false;
+    }
+
+    fn start(&mut self) {
+        if self.line.is_empty() {
+            self.line_depth = self.depth;
+        } else {
+            self.line.push(' ');
+        }
+    }
+
+    fn code(&mut self, run: &str) {
+        if !self.code_open {
+            self.flush();
+        }
+        self.start();
+        self.line.push_str(run);
+        self.code_open = true;
+    }
+
+    fn bracket(&mut self, op: u8) {
+        self.flush();
+        if op == b']' {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        self.line_depth = self.depth;
+        self.line.push(op as char);
+        if op == b'[' {
+            self.depth += 1;
+        }
+    }
+
+    fn comment(&mut self, text: &str) {
+        let mut lines = text.split('\n');
+        if let Some(first) = lines.next() {
+            let first = first.trim();
+            if !first.is_empty() {
+                self.start();
+                self.line.push_str(first);
+                self.flush();
+            }
+        }
+        for rest in lines {
+            self.flush();
+            let rest = rest.trim();
+            if !rest.is_empty() {
+                self.start();
+                self.line.push_str(rest);
+                self.flush();
+            }
+        }
+    }
+}
+
+pub fn format(bytes: &[u8]) -> Result<String, String> {
+    brackets::build_jumps(&parse::filter_ops(bytes))?;
+
+    let mut layout = Layout {
+        out: String::new(),
+        depth: 0,
+        line: String::new(),
+        line_depth: 0,
+        code_open: false,
+    };
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let mut end = i + 1;
+        if parse::is_op(b) {
+            if b == b'[' || b == b']' {
+                layout.bracket(b);
+                i = end;
+                continue;
+            }
+            let mut run = 1;
+            while end < bytes.len() && (bytes[end] == b || bytes[end].is_ascii_whitespace()) {
+                if bytes[end] == b {
+                    run += 1;
+                } else if !same_op_follows(bytes, end, b) {
+                    break;
+                }
+                end += 1;
+            }
+            layout.code(&(b as char).to_string().repeat(run));
+        } else {
+            while end < bytes.len() && !parse::is_op(bytes[end]) {
+                end += 1;
+            }
+            let text = String::from_utf8_lossy(&bytes[i..end]);
+            if !text.trim().is_empty() {
+                layout.comment(&text);
+            }
+        }
+        i = end;
+    }
+    layout.flush();
+    Ok(layout.out)
+}
+
+pub fn minify(bytes: &[u8]) -> Result<String, String> {
+    let ops = parse::filter_ops(bytes);
+    let jumps = brackets::build_jumps(&ops)?;
+
+    let mut out: Vec<u8> = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        let op = ops[i];
+        if op == b'[' && matches!(out.last(), None | Some(b']')) {
+            i = jumps[i] + 1;
+            continue;
+        }
+        match (out.last(), op) {
+            (Some(b'+'), b'-') | (Some(b'-'), b'+') | (Some(b'>'), b'<') => {
+                out.pop();
+            }
+            _ => out.push(op),
+        }
+        i += 1;
+    }
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+fn same_op_follows(bytes: &[u8], from: usize, op: u8) -> bool {
+    bytes[from..]
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|&b| b == op)
+}
@@ -0,0 +1,116 @@
+use crate::image::{self, Format};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_DIR: &str = "frames";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub offset: usize,
+    pub width: usize,
+    pub height: usize,
+    pub bytes_per_pixel: usize,
+    pub present: Option<u8>,
+    pub dir: PathBuf,
+    len: usize,
+    frames: usize,
+}
+
+impl Framebuffer {
+    pub fn new(
+        offset: usize,
+        width: usize,
+        height: usize,
+        bytes_per_pixel: usize,
+    ) -> Result<Framebuffer, String> {
+        if width == 0 || height == 0 {
+            return Err("framebuffer width and height must be greater than 0".to_string());
+        }
+        if bytes_per_pixel != 1 && bytes_per_pixel != 3 {
+            return Err(format!(
+                "framebuffer bytes per pixel must be 1 (grey) or 3 (RGB), got {}",
+                bytes_per_pixel
+            ));
+        }
+        let len = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(bytes_per_pixel))
+            .filter(|len| offset.checked_add(*len).is_some())
+            .ok_or_else(|| "framebuffer region overflows the tape".to_string())?;
+        Ok(Framebuffer {
+            offset,
+            width,
+            height,
+            bytes_per_pixel,
+            present: None,
+            dir: PathBuf::from(DEFAULT_DIR),
+            len,
+            frames: 0,
+        })
+    }
+
+    pub fn parse(spec: &str) -> Result<Framebuffer, String> {
+        let error = || format!("invalid framebuffer '{}': expected WxHxBPP[@OFFSET]", spec);
+        let (size, offset) = match spec.split_once('@') {
+            Some((size, offset)) => (size, offset.parse::<usize>().map_err(|_| error())?),
+            None => (spec, 0),
+        };
+        let dims = size
+            .split('x')
+            .map(|part| part.parse::<usize>().map_err(|_| error()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match dims[..] {
+            [width, height, bytes_per_pixel] => {
+                Framebuffer::new(offset, width, height, bytes_per_pixel)
+            }
+            _ => Err(error()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn rewind(&mut self) {
+        self.frames = 0;
+    }
+
+    pub fn format(&self) -> Format {
+        if self.bytes_per_pixel == 3 {
+            Format::Ppm
+        } else {
+            Format::Pgm
+        }
+    }
+
+    pub fn frame_path(&self, frame: usize) -> PathBuf {
+        let name = format!("frame-{:05}.{}", frame, image::extension(self.format()));
+        self.dir.join(name)
+    }
+
+    pub fn capture(&mut self, tape: &[u8]) -> Result<PathBuf, String> {
+        let start = self.offset.min(tape.len());
+        let end = (self.offset + self.len()).min(tape.len());
+        let bytes = image::encode(self.format(), self.width, self.height, &tape[start..end]);
+        if self.frames == 0 {
+            create_dir(&self.dir)?;
+        }
+        let path = self.frame_path(self.frames);
+        std::fs::write(&path, bytes)
+            .map_err(|e| format!("runtime error: failed to write '{}': {}", path.display(), e))?;
+        self.frames += 1;
+        Ok(path)
+    }
+}
+
+fn create_dir(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("runtime error: failed to create '{}': {}", dir.display(), e))
+}
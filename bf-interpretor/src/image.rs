@@ -0,0 +1,80 @@
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Pbm,
+    Pgm,
+    Ppm,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Result<Format, String> {
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("");
+        match ext.to_ascii_lowercase().as_str() {
+            "pbm" => Ok(Format::Pbm),
+            "pgm" => Ok(Format::Pgm),
+            "ppm" => Ok(Format::Ppm),
+            _ => Err(format!(
+                "unsupported image format '{}' (expected .pbm, .pgm or .ppm)",
+                path
+            )),
+        }
+    }
+}
+
+fn pixel(cells: &[u8], idx: usize) -> u8 {
+    cells.get(idx).copied().unwrap_or(0)
+}
+
+pub fn encode_pbm(width: usize, height: usize, cells: &[u8]) -> Vec<u8> {
+    let mut out = format!("P4\n{} {}\n", width, height).into_bytes();
+    for y in 0..height {
+        for chunk in 0..width.div_ceil(8) {
+            let mut byte = 0u8;
+            for bit in 0..8 {
+                let x = chunk * 8 + bit;
+                if x < width && pixel(cells, y * width + x) != 0 {
+                    byte |= 0x80 >> bit;
+                }
+            }
+            out.push(byte);
+        }
+    }
+    out
+}
+
+pub fn encode_pgm(width: usize, height: usize, cells: &[u8]) -> Vec<u8> {
+    let mut out = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+    out.extend((0..width * height).map(|idx| pixel(cells, idx)));
+    out
+}
+
+pub fn encode_ppm(width: usize, height: usize, cells: &[u8]) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    out.extend((0..width * height * 3).map(|idx| pixel(cells, idx)));
+    out
+}
+
+pub fn encode(format: Format, width: usize, height: usize, cells: &[u8]) -> Vec<u8> {
+    match format {
+        Format::Pbm => encode_pbm(width, height, cells),
+        Format::Pgm => encode_pgm(width, height, cells),
+        Format::Ppm => encode_ppm(width, height, cells),
+    }
+}
+
+pub fn save(path: &str, width: usize, height: usize, cells: &[u8]) -> Result<(), String> {
+    let bytes = encode(Format::from_path(path)?, width, height, cells);
+    std::fs::write(path, bytes).map_err(|e| format!("failed to write '{}': {}", path, e))
+}
+
+pub fn extension(format: Format) -> &'static str {
+    match format {
+        Format::Pbm => "pbm",
+        Format::Pgm => "pgm",
+        Format::Ppm => "ppm",
+    }
+}
@@ -54,6 +54,10 @@ impl<W: Write> Output<W> {
             .into_inner()
             .map_err(|e| format!("stdout flush failed: {}", e))
     }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
 }
 
 pub struct Debug<W: Write> {
@@ -1,9 +1,11 @@
+use crate::vm::Topology;
 use std::io::Write;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instr {
     Add(i32),
     Move(i32),
+    Vertical(i32),
     AddTo(i32, i32),
     AddMul(Vec<(i32, i32)>),
     Output,
@@ -12,9 +14,29 @@ pub enum Instr {
     Jnz(usize),
     SetZero,
     Scan(i32),
+    ScanVertical(i32),
+    Define(usize),
+    Return,
+    Call,
+    Fork,
+    Present,
+    End,
+    Store,
+    Load,
+    Shl,
+    Shr,
+    Not,
+    Xor,
+    And,
+    Or,
 }
 
 pub fn build(ops: &[u8], jumps: &[usize]) -> Result<Vec<Instr>, String> {
+    build_for(ops, jumps, &Topology::Linear)
+}
+
+pub fn build_for(ops: &[u8], jumps: &[usize], topology: &Topology) -> Result<Vec<Instr>, String> {
+    let grid = topology.is_grid();
     if ops.len() != jumps.len() {
         return Err("ops and jumps length mismatch".to_string());
     }
@@ -22,6 +44,8 @@ pub fn build(ops: &[u8], jumps: &[usize]) -> Result<Vec<Instr>, String> {
     let mut ir = Vec::with_capacity(ops.len());
     for (idx, &op) in ops.iter().enumerate() {
         match op {
+            b'^' if grid => ir.push(Instr::Vertical(-1)),
+            b'v' if grid => ir.push(Instr::Vertical(1)),
             b'+' => ir.push(Instr::Add(1)),
             b'-' => ir.push(Instr::Add(-1)),
             b'>' => ir.push(Instr::Move(1)),
@@ -42,6 +66,26 @@ pub fn build(ops: &[u8], jumps: &[usize]) -> Result<Vec<Instr>, String> {
                 }
                 ir.push(Instr::Jnz(target));
             }
+            b'(' => {
+                let target = jumps[idx];
+                if target == usize::MAX {
+                    return Err(format!("missing jump target for '(' at {}", idx));
+                }
+                ir.push(Instr::Define(target));
+            }
+            b')' => ir.push(Instr::Return),
+            b':' => ir.push(Instr::Call),
+            b'Y' => ir.push(Instr::Fork),
+            b'#' => ir.push(Instr::Present),
+            b'@' => ir.push(Instr::End),
+            b'$' => ir.push(Instr::Store),
+            b'!' => ir.push(Instr::Load),
+            b'{' => ir.push(Instr::Shl),
+            b'}' => ir.push(Instr::Shr),
+            b'~' => ir.push(Instr::Not),
+            b'^' => ir.push(Instr::Xor),
+            b'&' => ir.push(Instr::And),
+            b'|' => ir.push(Instr::Or),
             _ => {}
         }
     }
@@ -53,6 +97,7 @@ pub fn dump_ir<W: Write>(ir: &[Instr], out: &mut W) -> Result<(), String> {
         let result: Result<(), std::io::Error> = match instr {
             Instr::Add(delta) => writeln!(out, "{} Add {}", idx, delta),
             Instr::Move(delta) => writeln!(out, "{} Move {}", idx, delta),
+            Instr::Vertical(delta) => writeln!(out, "{} Vertical {}", idx, delta),
             Instr::AddTo(offset, sign) => writeln!(out, "{} AddTo {} {}", idx, offset, sign),
             Instr::AddMul(edits) => (|| {
                 write!(out, "{} AddMul", idx)?;
@@ -67,8 +112,96 @@ pub fn dump_ir<W: Write>(ir: &[Instr], out: &mut W) -> Result<(), String> {
             Instr::Jnz(target) => writeln!(out, "{} Jnz {}", idx, target),
             Instr::SetZero => writeln!(out, "{} SetZero", idx),
             Instr::Scan(dir) => writeln!(out, "{} Scan {}", idx, dir),
+            Instr::ScanVertical(dir) => writeln!(out, "{} ScanVertical {}", idx, dir),
+            Instr::Define(end) => writeln!(out, "{} Define {}", idx, end),
+            Instr::Return => writeln!(out, "{} Return", idx),
+            Instr::Call => writeln!(out, "{} Call", idx),
+            Instr::Fork => writeln!(out, "{} Fork", idx),
+            Instr::Present => writeln!(out, "{} Present", idx),
+            Instr::End => writeln!(out, "{} End", idx),
+            Instr::Store => writeln!(out, "{} Store", idx),
+            Instr::Load => writeln!(out, "{} Load", idx),
+            Instr::Shl => writeln!(out, "{} Shl", idx),
+            Instr::Shr => writeln!(out, "{} Shr", idx),
+            Instr::Not => writeln!(out, "{} Not", idx),
+            Instr::Xor => writeln!(out, "{} Xor", idx),
+            Instr::And => writeln!(out, "{} And", idx),
+            Instr::Or => writeln!(out, "{} Or", idx),
         };
         result.map_err(|e| format!("stderr write failed: {}", e))?;
     }
     Ok(())
 }
+
+pub fn emit_bf(ir: &[Instr]) -> Result<String, String> {
+    let mut out = String::new();
+    for (idx, instr) in ir.iter().enumerate() {
+        match instr {
+            Instr::Add(delta) => push_repeat(&mut out, '+', '-', *delta),
+            Instr::Move(delta) => push_repeat(&mut out, '>', '<', *delta),
+            Instr::Vertical(delta) => push_repeat(&mut out, 'v', '^', *delta),
+            Instr::AddTo(offset, sign) => {
+                out.push_str("[-");
+                push_repeat(&mut out, '>', '<', *offset);
+                out.push(if *sign < 0 { '-' } else { '+' });
+                push_repeat(&mut out, '<', '>', *offset);
+                out.push(']');
+            }
+            Instr::AddMul(edits) => {
+                out.push('[');
+                let mut at = 0i64;
+                for (offset, factor) in edits {
+                    push_repeat(&mut out, '>', '<', (*offset as i64 - at) as i32);
+                    push_repeat(&mut out, '+', '-', *factor);
+                    at = *offset as i64;
+                }
+                push_repeat(&mut out, '<', '>', at as i32);
+                out.push_str("-]");
+            }
+            Instr::Output => out.push('.'),
+            Instr::Input => out.push(','),
+            Instr::Jz(_) => out.push('['),
+            Instr::Jnz(_) => out.push(']'),
+            Instr::SetZero => out.push_str("[-]"),
+            Instr::Scan(dir) => {
+                if *dir == 0 {
+                    return Err(format!("cannot emit Scan 0 at {}", idx));
+                }
+                out.push('[');
+                push_repeat(&mut out, '>', '<', *dir);
+                out.push(']');
+            }
+            Instr::ScanVertical(dir) => {
+                if *dir == 0 {
+                    return Err(format!("cannot emit ScanVertical 0 at {}", idx));
+                }
+                out.push('[');
+                push_repeat(&mut out, 'v', '^', *dir);
+                out.push(']');
+            }
+            Instr::Define(_) => out.push('('),
+            Instr::Return => out.push(')'),
+            Instr::Call => out.push(':'),
+            Instr::Fork => out.push('Y'),
+            Instr::Present => out.push('#'),
+            Instr::End => out.push('@'),
+            Instr::Store => out.push('$'),
+            Instr::Load => out.push('!'),
+            Instr::Shl => out.push('{'),
+            Instr::Shr => out.push('}'),
+            Instr::Not => out.push('~'),
+            Instr::Xor => out.push('^'),
+            Instr::And => out.push('&'),
+            Instr::Or => out.push('|'),
+        }
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+fn push_repeat(out: &mut String, up: char, down: char, count: i32) {
+    let ch = if count < 0 { down } else { up };
+    for _ in 0..count.unsigned_abs() {
+        out.push(ch);
+    }
+}
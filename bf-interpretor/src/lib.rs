@@ -1,30 +1,124 @@
+pub mod bfpp;
 pub mod brackets;
+pub mod dap;
+pub mod dialect;
+pub mod format;
+pub mod framebuffer;
+pub mod image;
 pub mod io;
 pub mod ir;
+pub mod json;
 pub mod opt;
 pub mod parse;
+pub mod srcmap;
 pub mod vm;
 
-pub fn run_file(
-    path: &str,
-    tape_size: usize,
-    max_steps: Option<u64>,
-    dump_ir: bool,
-    trace: bool,
+const DEFAULT_GRID_WIDTH: usize = 64;
+
+pub struct Program {
+    pub ir: Vec<ir::Instr>,
+    pub positions: Vec<usize>,
+}
+
+pub fn compile(bytes: &[u8], no_opt: bool) -> Result<Program, String> {
+    compile_ops(&parse::filter_ops(bytes), parse::op_offsets(bytes), no_opt)
+}
+
+pub fn compile_ops(ops: &[u8], positions: Vec<usize>, no_opt: bool) -> Result<Program, String> {
+    compile_ops_for(ops, positions, no_opt, &vm::Topology::Linear)
+}
+
+pub fn compile_ops_for(
+    ops: &[u8],
+    mut positions: Vec<usize>,
     no_opt: bool,
-) -> Result<(), String> {
-    let bytes =
-        std::fs::read(path).map_err(|e| format!("failed to read '{}': {}", path, e))?;
-    let ops = parse::filter_ops(&bytes);
-    let jumps = brackets::build_jumps(&ops)?;
-    let mut ir = ir::build(&ops, &jumps)?;
+    topology: &vm::Topology,
+) -> Result<Program, String> {
+    let jumps = brackets::build_jumps(ops)?;
+    let mut ir = ir::build_for(ops, &jumps, topology)?;
     if !no_opt {
-        opt::merge_ops(&mut ir);
-        opt::rebuild_jumps(&mut ir)?;
-        opt::loop_analysis(&mut ir);
-        opt::peephole(&mut ir);
-        opt::rebuild_jumps(&mut ir)?;
+        opt::optimize_for(&mut ir, &mut positions, topology)?;
     }
+    Ok(Program { ir, positions })
+}
+
+pub struct RunOptions {
+    pub tape_size: usize,
+    pub max_steps: Option<u64>,
+    pub dump_ir: bool,
+    pub trace: bool,
+    pub no_opt: bool,
+    pub watches: Vec<vm::Watch>,
+    pub source_map: Option<String>,
+    pub preprocess: bool,
+    pub dialect: Option<dialect::Dialect>,
+    pub schedule: vm::Schedule,
+    pub grid: Option<usize>,
+    pub grid_dump: Option<String>,
+    pub framebuffer: Option<framebuffer::Framebuffer>,
+}
+
+pub fn run_file(path: &str, options: &RunOptions) -> Result<(), String> {
+    let (bytes, source_map) = if options.preprocess || path.ends_with(".bfpp") {
+        if options.source_map.is_some() || options.dialect.is_some() {
+            return Err("--source-map and --dialect cannot be used with BFPP input".to_string());
+        }
+        let bfpp_options = bfpp::Options {
+            include_dirs: bfpp::include::env_search_path(),
+            ..bfpp::Options::default()
+        };
+        let processed = bfpp::driver::preprocess_mapped(std::path::Path::new(path), &bfpp_options)
+            .map_err(|e| e.to_string())?;
+        let source_map = processed.source_map();
+        (processed.text.into_bytes(), Some(source_map))
+    } else {
+        let bytes =
+            std::fs::read(path).map_err(|e| format!("failed to read '{}': {}", path, e))?;
+        let source_map = match &options.source_map {
+            Some(map_path) => Some(srcmap::SourceMap::load(map_path)?),
+            None => None,
+        };
+        (bytes, source_map)
+    };
+    let locate = |offset: usize| match source_map.as_ref().and_then(|map| map.lookup(offset)) {
+        Some(location) => location.to_string(),
+        None => {
+            let (line, col) = parse::line_col(&bytes, offset);
+            format!("{}:{}:{}", path, line, col)
+        }
+    };
+    let (mut ops, mut offsets) = match &options.dialect {
+        Some(dialect) => dialect.tokenize(&bytes),
+        None => (parse::filter_ops(&bytes), parse::op_offsets(&bytes)),
+    };
+    let supports = |op: u8| options.dialect.as_ref().is_some_and(|d| d.supports(op));
+    let mut tape_data: &[u8] = &[];
+    if let Some(end) = ops.iter().position(|&op| op == b'@').filter(|_| supports(b'@')) {
+        tape_data = &bytes[offsets[end] + 1..];
+        ops.truncate(end + 1);
+        offsets.truncate(end + 1);
+    }
+    let two_dimensional = supports(b'v');
+    let topology = match options.grid {
+        Some(_) if supports(b'^') && !two_dimensional => {
+            return Err("--grid cannot be used with a dialect that uses '^' for XOR".to_string());
+        }
+        Some(width) => vm::Topology::grid(width)?,
+        None if two_dimensional => vm::Topology::grid(DEFAULT_GRID_WIDTH)?,
+        None => vm::Topology::Linear,
+    };
+    if options.grid_dump.is_some() && topology == vm::Topology::Linear {
+        return Err("--grid-dump needs --grid or --dialect 2d".to_string());
+    }
+    let Program { ir, positions } =
+        compile_ops_for(&ops, offsets.clone(), options.no_opt, &topology).map_err(|err| {
+            match brackets::match_brackets(&ops) {
+                Err(bracket) if source_map.is_some() || options.dialect.is_some() => {
+                    format!("{} ({})", err, locate(offsets[bracket.index]))
+                }
+                _ => err,
+            }
+        })?;
 
     let stdin = std::io::stdin();
     let stdout = std::io::stdout();
@@ -33,24 +127,62 @@ pub fn run_file(
     let mut output = io::Output::new(stdout.lock());
     let mut debug = io::Debug::new(stderr.lock());
 
-    if dump_ir {
+    if options.dump_ir {
         ir::dump_ir(&ir, debug.writer())?;
         debug.flush()?;
     }
 
-    let mut machine = vm::Vm::with_capacity(tape_size)?;
-    vm::Vm::run_ir(
+    let mut machine = vm::Vm::with_capacity(options.tape_size)?;
+    machine.set_schedule(options.schedule)?;
+    machine.set_topology(topology);
+    machine.set_initial_tape(tape_data)?;
+    if let Some(framebuffer) = &options.framebuffer {
+        machine.set_framebuffer(framebuffer.clone());
+    }
+    for watch in &options.watches {
+        machine.add_watch(*watch);
+    }
+    if options.trace && source_map.is_some() {
+        machine.set_trace_labels(positions.iter().map(|&offset| locate(offset)).collect());
+    }
+    let result = vm::Vm::run_ir(
         &mut machine,
         &ir,
         &mut input,
         &mut output,
-        if trace { Some(&mut debug) } else { None },
-        max_steps,
-    )?;
+        if options.trace { Some(&mut debug) } else { None },
+        options.max_steps,
+    );
+    let halt = match result {
+        Ok(halt) => halt,
+        Err(err) => match positions.get(machine.ip()) {
+            Some(&offset) if source_map.is_some() => {
+                return Err(format!("{} at {}", err, locate(offset)));
+            }
+            _ => return Err(err),
+        },
+    };
 
-    if trace {
+    if let vm::Halt::Watch(hit) = halt {
+        debug.write_fmt(format_args!(
+            "watchpoint: cell={} old={} new={} step={} ip={} at {}\n",
+            hit.watch.cell,
+            hit.old,
+            hit.new,
+            hit.step,
+            hit.ip,
+            locate(positions[hit.ip])
+        ))?;
         debug.flush()?;
     }
 
+    if options.trace {
+        debug.flush()?;
+    }
+
+    if let (Some(path), vm::Topology::Grid { width }) = (&options.grid_dump, topology) {
+        image::save(path, width, machine.grid_rows(), machine.tape())?;
+    }
+
     Ok(())
 }
@@ -1,7 +1,10 @@
 use std::env;
 use std::process;
 
-use bf::run_file;
+use bf::dialect::Dialect;
+use bf::framebuffer::Framebuffer;
+use bf::{run_file, RunOptions};
+use bf::vm::{Schedule, Topology, Watch, WatchKind};
 
 const DEFAULT_TAPE_SIZE: usize = 30_000;
 const TAPE_FLAG: &str = "--tape";
@@ -11,12 +14,54 @@ const MAX_STEPS_FLAG_EQ: &str = "--max-steps=";
 const DUMP_IR_FLAG: &str = "--dump-ir";
 const TRACE_FLAG: &str = "--trace";
 const NO_OPT_FLAG: &str = "--no-opt";
+const WATCH_FLAG: &str = "--watch";
+const WATCH_FLAG_EQ: &str = "--watch=";
+const SOURCE_MAP_FLAG: &str = "--source-map";
+const SOURCE_MAP_FLAG_EQ: &str = "--source-map=";
+const PREPROCESS_FLAG: &str = "--preprocess";
+const DIALECT_FLAG: &str = "--dialect";
+const DIALECT_FLAG_EQ: &str = "--dialect=";
+const QUANTUM_FLAG: &str = "--quantum";
+const QUANTUM_FLAG_EQ: &str = "--quantum=";
+const SEED_FLAG: &str = "--seed";
+const SEED_FLAG_EQ: &str = "--seed=";
+const GRID_FLAG: &str = "--grid";
+const GRID_FLAG_EQ: &str = "--grid=";
+const GRID_DUMP_FLAG: &str = "--grid-dump";
+const GRID_DUMP_FLAG_EQ: &str = "--grid-dump=";
+const FRAMEBUFFER_FLAG: &str = "--framebuffer";
+const FRAMEBUFFER_FLAG_EQ: &str = "--framebuffer=";
+const FRAMES_FLAG: &str = "--frames";
+const FRAMES_FLAG_EQ: &str = "--frames=";
+const HELP_FLAG: &str = "--help";
+const PRESENT_FLAG: &str = "--present";
+const PRESENT_FLAG_EQ: &str = "--present=";
+const FROM_FLAG: &str = "--from";
+const TO_FLAG: &str = "--to";
 const EXIT_USAGE: i32 = 2;
 const EXIT_RUNTIME: i32 = 1;
 const MAX_STEPS_ERROR: &str = "max steps";
 
+const DAP_COMMAND: &str = "dap";
+const FMT_COMMAND: &str = "fmt";
+const MINIFY_COMMAND: &str = "minify";
+const EMIT_BF_COMMAND: &str = "emit-bf";
+const TRANSLATE_COMMAND: &str = "translate";
+const OUTPUT_FLAG: &str = "-o";
+
 fn usage() -> &'static str {
-    "Usage: bf <file> [--tape N] [--max-steps N] [--dump-ir] [--trace] [--no-opt]"
+    "Usage: bf <file> [--tape N] [--max-steps N] [--dump-ir] [--trace] [--no-opt] [--watch cell=N[:value]] [--source-map <file.map>] [--preprocess] [--dialect <name|table>] [--quantum N] [--seed N] [--grid WIDTH] [--grid-dump <file.pbm|file.pgm>] [--framebuffer WxHxBPP[@OFFSET]] [--frames <dir>] [--present N]\n       bf dap\n       bf fmt <file> [-o <output>]\n       bf minify <file> [-o <output>]\n       bf emit-bf <file> [-o <output>]\n       bf translate <file> --to <name|table> [--from <name|table>] [-o <output>]"
+}
+
+fn help() -> String {
+    let notes = [
+        "--dialect <a+b>  combine extension dialects, e.g. --dialect 2d+framebuffer",
+        "--framebuffer    map a W x H framebuffer (BPP 1 = grey, 3 = RGB) onto the tape at OFFSET",
+        "--frames <dir>   write captured frames to <dir> (default frames/)",
+        "--present N      capture a frame whenever `.` outputs byte N; that byte is swallowed and",
+        "                 never reaches stdout, so pick one the program does not print",
+    ];
+    format!("{}\n\n{}", usage(), notes.join("\n"))
 }
 
 fn parse_tape_size(value: &str) -> Result<usize, String> {
@@ -41,7 +86,189 @@ fn parse_max_steps(value: &str) -> Result<u64, String> {
     }
 }
 
+fn parse_quantum(value: &str) -> Result<u64, String> {
+    let parsed = value
+        .parse::<u64>()
+        .map_err(|e| format!("invalid quantum '{}': {}", value, e))?;
+    if parsed == 0 {
+        Err("quantum must be greater than 0".to_string())
+    } else {
+        Ok(parsed)
+    }
+}
+
+fn parse_grid_width(value: &str) -> Result<usize, String> {
+    let parsed = value
+        .parse::<usize>()
+        .map_err(|e| format!("invalid grid width '{}': {}", value, e))?;
+    Topology::grid(parsed)?;
+    Ok(parsed)
+}
+
+fn parse_seed(value: &str) -> Result<u64, String> {
+    value
+        .parse::<u64>()
+        .map_err(|e| format!("invalid seed '{}': {}", value, e))
+}
+
+fn parse_cell_value(value: &str, spec: &str) -> Result<u8, String> {
+    value
+        .parse::<u8>()
+        .map_err(|e| format!("invalid watch value in '{}': {}", spec, e))
+}
+
+fn parse_watch(spec: &str) -> Result<Watch, String> {
+    let rest = spec
+        .strip_prefix("cell=")
+        .ok_or_else(|| format!("invalid watch '{}': expected cell=N[:value]", spec))?;
+    let (cell, cond) = match rest.split_once(':') {
+        Some((cell, cond)) => (cell, Some(cond)),
+        None => (rest, None),
+    };
+    let cell = cell
+        .parse::<usize>()
+        .map_err(|e| format!("invalid watch cell in '{}': {}", spec, e))?;
+    let kind = match cond {
+        None => WatchKind::Write,
+        Some("change") => WatchKind::Change,
+        Some(cond) => match cond.split_once("..") {
+            Some((lo, hi)) => {
+                let lo = parse_cell_value(lo, spec)?;
+                let hi = parse_cell_value(hi, spec)?;
+                if lo > hi {
+                    return Err(format!("invalid watch range in '{}'", spec));
+                }
+                WatchKind::Range(lo, hi)
+            }
+            None => WatchKind::Equals(parse_cell_value(cond, spec)?),
+        },
+    };
+    Ok(Watch { cell, kind })
+}
+
+fn run_dap() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    if let Err(err) = bf::dap::serve(stdin.lock(), stdout.lock()) {
+        eprintln!("{}", err);
+        process::exit(EXIT_RUNTIME);
+    }
+}
+
+fn run_source_tool(command: &str, args: &[String]) {
+    let (input, output) = match args {
+        [input] => (input, None),
+        [input, flag, output] if flag == OUTPUT_FLAG => (input, Some(output)),
+        _ => {
+            eprintln!("{}", usage());
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let result = std::fs::read(input)
+        .map_err(|e| format!("failed to read '{}': {}", input, e))
+        .and_then(|bytes| {
+            match command {
+                FMT_COMMAND => bf::format::format(&bytes),
+                MINIFY_COMMAND => bf::format::minify(&bytes),
+                _ => bf::compile(&bytes, false).and_then(|program| bf::ir::emit_bf(&program.ir)),
+            }
+        })
+        .and_then(|text| match output {
+            Some(path) => std::fs::write(path, text)
+                .map_err(|e| format!("failed to write '{}': {}", path, e)),
+            None => {
+                print!("{}", text);
+                Ok(())
+            }
+        });
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(EXIT_RUNTIME);
+    }
+}
+
+fn run_translate(args: &[String]) {
+    let mut input = None;
+    let mut from = None;
+    let mut to = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let slot = match arg.as_str() {
+            FROM_FLAG => &mut from,
+            TO_FLAG => &mut to,
+            OUTPUT_FLAG => &mut output,
+            _ if arg.starts_with('-') || input.is_some() => {
+                eprintln!("{}", usage());
+                process::exit(EXIT_USAGE);
+            }
+            _ => {
+                input = Some(arg);
+                continue;
+            }
+        };
+        match args.next() {
+            Some(value) if slot.is_none() => *slot = Some(value),
+            _ => {
+                eprintln!("{}", usage());
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    let (input, to) = match (input, to) {
+        (Some(input), Some(to)) => (input, to),
+        _ => {
+            eprintln!("{}", usage());
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let result = Dialect::resolve(to).and_then(|to| {
+        let from = match from {
+            Some(from) => Dialect::resolve(from)?,
+            None => Dialect::brainfuck(),
+        };
+        let bytes = std::fs::read(input)
+            .map_err(|e| format!("failed to read '{}': {}", input, e))?;
+        let text = bf::dialect::translate(&bytes, &from, &to)?;
+        match output {
+            Some(path) => std::fs::write(path, text)
+                .map_err(|e| format!("failed to write '{}': {}", path, e)),
+            None => {
+                print!("{}", text);
+                Ok(())
+            }
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(EXIT_RUNTIME);
+    }
+}
+
 fn main() {
+    let command = env::args().nth(1);
+    if command.as_deref() == Some(TRANSLATE_COMMAND) {
+        let rest: Vec<String> = env::args().skip(2).collect();
+        run_translate(&rest);
+        return;
+    }
+    if let Some(command) = command.as_deref() {
+        if command == FMT_COMMAND || command == MINIFY_COMMAND || command == EMIT_BF_COMMAND {
+            let rest: Vec<String> = env::args().skip(2).collect();
+            run_source_tool(command, &rest);
+            return;
+        }
+    }
+
+    if env::args().nth(1).as_deref() == Some(DAP_COMMAND) {
+        if env::args().len() != 2 {
+            eprintln!("{}", usage());
+            process::exit(EXIT_USAGE);
+        }
+        run_dap();
+        return;
+    }
+
     let mut args = env::args().skip(1);
     let mut tape_size = DEFAULT_TAPE_SIZE;
     let mut tape_specified = false;
@@ -50,9 +277,24 @@ fn main() {
     let mut dump_ir = false;
     let mut trace = false;
     let mut no_opt = false;
+    let mut watches = Vec::new();
+    let mut source_map = None;
+    let mut preprocess = false;
+    let mut dialect = None;
+    let mut quantum = None;
+    let mut seed = None;
+    let mut grid = None;
+    let mut grid_dump = None;
+    let mut framebuffer = None;
+    let mut frames = None;
+    let mut present = None;
     let mut path = None;
 
     while let Some(arg) = args.next() {
+        if arg == HELP_FLAG || arg == "-h" {
+            println!("{}", help());
+            return;
+        }
         if arg == TAPE_FLAG {
             let value = match args.next() {
                 Some(value) => value,
@@ -146,6 +388,257 @@ fn main() {
             continue;
         }
 
+        if arg == PREPROCESS_FLAG {
+            preprocess = true;
+            continue;
+        }
+
+        if arg == DIALECT_FLAG || arg.starts_with(DIALECT_FLAG_EQ) {
+            let value = match arg.strip_prefix(DIALECT_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), DIALECT_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if dialect.is_some() {
+                eprintln!("{}: dialect already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            dialect = match Dialect::resolve(&value) {
+                Ok(dialect) => Some(dialect),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            continue;
+        }
+
+        if arg == QUANTUM_FLAG || arg.starts_with(QUANTUM_FLAG_EQ) {
+            let value = match arg.strip_prefix(QUANTUM_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), QUANTUM_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if quantum.is_some() {
+                eprintln!("{}: quantum already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            quantum = match parse_quantum(&value) {
+                Ok(quantum) => Some(quantum),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            continue;
+        }
+
+        if arg == SEED_FLAG || arg.starts_with(SEED_FLAG_EQ) {
+            let value = match arg.strip_prefix(SEED_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), SEED_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if seed.is_some() {
+                eprintln!("{}: seed already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            seed = match parse_seed(&value) {
+                Ok(seed) => Some(seed),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            continue;
+        }
+
+        if arg == GRID_FLAG || arg.starts_with(GRID_FLAG_EQ) {
+            let value = match arg.strip_prefix(GRID_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), GRID_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if grid.is_some() {
+                eprintln!("{}: grid width already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            grid = match parse_grid_width(&value) {
+                Ok(width) => Some(width),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            continue;
+        }
+
+        if arg == GRID_DUMP_FLAG || arg.starts_with(GRID_DUMP_FLAG_EQ) {
+            let value = match arg.strip_prefix(GRID_DUMP_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), GRID_DUMP_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if grid_dump.is_some() {
+                eprintln!("{}: grid dump already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            if let Err(err) = bf::image::Format::from_path(&value) {
+                eprintln!("{}", err);
+                process::exit(EXIT_USAGE);
+            }
+            grid_dump = Some(value);
+            continue;
+        }
+
+        if arg == FRAMEBUFFER_FLAG || arg.starts_with(FRAMEBUFFER_FLAG_EQ) {
+            let value = match arg.strip_prefix(FRAMEBUFFER_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), FRAMEBUFFER_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if framebuffer.is_some() {
+                eprintln!("{}: framebuffer already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            framebuffer = match Framebuffer::parse(&value) {
+                Ok(fb) => Some(fb),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            continue;
+        }
+
+        if arg == FRAMES_FLAG || arg.starts_with(FRAMES_FLAG_EQ) {
+            let value = match arg.strip_prefix(FRAMES_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), FRAMES_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if frames.is_some() {
+                eprintln!("{}: frames directory already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            frames = Some(value);
+            continue;
+        }
+
+        if arg == PRESENT_FLAG || arg.starts_with(PRESENT_FLAG_EQ) {
+            let value = match arg.strip_prefix(PRESENT_FLAG_EQ) {
+                Some(rest) => rest.to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{}: missing value for {}", usage(), PRESENT_FLAG);
+                        process::exit(EXIT_USAGE);
+                    }
+                },
+            };
+            if present.is_some() {
+                eprintln!("{}: present byte already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            present = match value.parse::<u8>() {
+                Ok(byte) => Some(byte),
+                Err(e) => {
+                    eprintln!("invalid present byte '{}': {}", value, e);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            continue;
+        }
+
+        if arg == WATCH_FLAG {
+            let value = match args.next() {
+                Some(value) => value,
+                None => {
+                    eprintln!("{}: missing value for {}", usage(), WATCH_FLAG);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            match parse_watch(&value) {
+                Ok(watch) => watches.push(watch),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(EXIT_USAGE);
+                }
+            }
+            continue;
+        }
+
+        if let Some(rest) = arg.strip_prefix(WATCH_FLAG_EQ) {
+            match parse_watch(rest) {
+                Ok(watch) => watches.push(watch),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(EXIT_USAGE);
+                }
+            }
+            continue;
+        }
+
+        if arg == SOURCE_MAP_FLAG {
+            let value = match args.next() {
+                Some(value) => value,
+                None => {
+                    eprintln!("{}: missing value for {}", usage(), SOURCE_MAP_FLAG);
+                    process::exit(EXIT_USAGE);
+                }
+            };
+            if source_map.is_some() {
+                eprintln!("{}: source map already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            source_map = Some(value);
+            continue;
+        }
+
+        if let Some(rest) = arg.strip_prefix(SOURCE_MAP_FLAG_EQ) {
+            if source_map.is_some() {
+                eprintln!("{}: source map already set", usage());
+                process::exit(EXIT_USAGE);
+            }
+            source_map = Some(rest.to_string());
+            continue;
+        }
+
         if arg.starts_with('-') {
             eprintln!("{}", usage());
             process::exit(EXIT_USAGE);
@@ -166,7 +659,41 @@ fn main() {
         }
     };
 
-    if let Err(err) = run_file(&path, tape_size, max_steps, dump_ir, trace, no_opt) {
+    let framebuffer = match framebuffer {
+        Some(mut fb) => {
+            if let Some(dir) = frames {
+                fb.dir = dir.into();
+            }
+            fb.present = present;
+            Some(fb)
+        }
+        None if frames.is_some() || present.is_some() => {
+            eprintln!("{}: --frames and --present need --framebuffer", usage());
+            process::exit(EXIT_USAGE);
+        }
+        None => None,
+    };
+    let quantum = quantum.unwrap_or(1);
+    let schedule = match seed {
+        Some(seed) => Schedule::Random { quantum, seed },
+        None => Schedule::RoundRobin { quantum },
+    };
+    let options = RunOptions {
+        tape_size,
+        max_steps,
+        dump_ir,
+        trace,
+        no_opt,
+        watches,
+        source_map,
+        preprocess,
+        dialect,
+        schedule,
+        grid,
+        grid_dump,
+        framebuffer,
+    };
+    if let Err(err) = run_file(&path, &options) {
         eprintln!("{}", err);
         if err.contains(MAX_STEPS_ERROR) {
             process::exit(1);
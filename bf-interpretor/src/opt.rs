@@ -1,12 +1,48 @@
 use crate::ir::Instr;
+use crate::vm::Topology;
 use std::collections::BTreeMap;
 
 pub fn merge_ops(ir: &mut Vec<Instr>) {
-    merge_linear(ir);
+    let mut positions = identity_positions(ir.len());
+    merge_linear(ir, &mut positions);
 }
 
 pub fn loop_analysis(ir: &mut Vec<Instr>) {
+    let mut positions = identity_positions(ir.len());
+    analyze_loops(ir, &mut positions, &Topology::Linear);
+}
+
+pub fn peephole(ir: &mut Vec<Instr>) {
+    let mut positions = identity_positions(ir.len());
+    merge_linear(ir, &mut positions);
+}
+
+pub fn optimize(ir: &mut Vec<Instr>, positions: &mut Vec<usize>) -> Result<(), String> {
+    optimize_for(ir, positions, &Topology::Linear)
+}
+
+pub fn optimize_for(
+    ir: &mut Vec<Instr>,
+    positions: &mut Vec<usize>,
+    topology: &Topology,
+) -> Result<(), String> {
+    if positions.len() != ir.len() {
+        return Err("ir and positions length mismatch".to_string());
+    }
+    merge_linear(ir, positions);
+    rebuild_jumps(ir)?;
+    analyze_loops(ir, positions, topology);
+    merge_linear(ir, positions);
+    rebuild_jumps(ir)
+}
+
+fn identity_positions(len: usize) -> Vec<usize> {
+    (0..len).collect()
+}
+
+fn analyze_loops(ir: &mut Vec<Instr>, positions: &mut Vec<usize>, topology: &Topology) {
     let mut out = Vec::with_capacity(ir.len());
+    let mut out_pos = Vec::with_capacity(ir.len());
     let mut i = 0;
     while i < ir.len() {
         if i + 5 < ir.len() {
@@ -20,10 +56,13 @@ pub fn loop_analysis(ir: &mut Vec<Instr>) {
                                     if *sign == 1 || *sign == -1 {
                                         if let Instr::Move(back) = &ir[i + 4] {
                                             if let Some(expected) = offset.checked_neg() {
-                                                if *back == expected {
+                                                if *back == expected
+                                                    && topology.distinct(&[*offset])
+                                                {
                                                     if let Instr::Jnz(back_target) = &ir[i + 5] {
                                                         if *back_target == i {
                                                             out.push(Instr::AddTo(*offset, *sign));
+                                                            out_pos.push(positions[i]);
                                                             i += 6;
                                                             continue;
                                                         }
@@ -44,14 +83,20 @@ pub fn loop_analysis(ir: &mut Vec<Instr>) {
             if let Instr::Jz(target) = &ir[i] {
                 let target = *target;
                 if target == i + 2 {
-                    if let Instr::Move(dir) = &ir[i + 1] {
-                        if *dir == 1 || *dir == -1 {
-                            if let Instr::Jnz(back) = &ir[i + 2] {
-                                if *back == i {
-                                    out.push(Instr::Scan(*dir));
-                                    i += 3;
-                                    continue;
-                                }
+                    let scan = match &ir[i + 1] {
+                        Instr::Move(dir) if *dir == 1 || *dir == -1 => Some(Instr::Scan(*dir)),
+                        Instr::Vertical(dir) if *dir == 1 || *dir == -1 => {
+                            Some(Instr::ScanVertical(*dir))
+                        }
+                        _ => None,
+                    };
+                    if let Some(scan) = scan {
+                        if let Instr::Jnz(back) = &ir[i + 2] {
+                            if *back == i {
+                                out.push(scan);
+                                out_pos.push(positions[i]);
+                                i += 3;
+                                continue;
                             }
                         }
                     }
@@ -68,6 +113,7 @@ pub fn loop_analysis(ir: &mut Vec<Instr>) {
                             if let Instr::Jnz(back) = &ir[i + 2] {
                                 if *back == i {
                                     out.push(Instr::SetZero);
+                                    out_pos.push(positions[i]);
                                     i += 3;
                                     continue;
                                 }
@@ -78,50 +124,64 @@ pub fn loop_analysis(ir: &mut Vec<Instr>) {
             }
         }
 
-        if let Some((next, edits)) = try_addmul_loop(ir, i) {
-            out.push(Instr::AddMul(edits));
+        if let Some((next, edits)) = try_addmul_loop(ir, i, topology) {
+            if edits.is_empty() {
+                out.push(Instr::SetZero);
+            } else {
+                out.push(Instr::AddMul(edits));
+            }
+            out_pos.push(positions[i]);
             i = next;
             continue;
         }
 
         out.push(ir[i].clone());
+        out_pos.push(positions[i]);
         i += 1;
     }
     *ir = out;
+    *positions = out_pos;
 }
 
-pub fn peephole(ir: &mut Vec<Instr>) {
-    merge_linear(ir);
-}
-
-pub fn rebuild_jumps(ir: &mut Vec<Instr>) -> Result<(), String> {
+pub fn rebuild_jumps(ir: &mut [Instr]) -> Result<(), String> {
     let mut stack = Vec::new();
     for idx in 0..ir.len() {
         let instr = ir[idx].clone();
         match instr {
-            Instr::Jz(_) => stack.push(idx),
+            Instr::Jz(_) | Instr::Define(_) => stack.push(idx),
             Instr::Jnz(_) => {
-                let open = stack
-                    .pop()
-                    .ok_or_else(|| format!("unmatched ']' at {}", idx))?;
+                let open = match stack.pop() {
+                    Some(open) if matches!(ir[open], Instr::Jz(_)) => open,
+                    _ => return Err(format!("unmatched ']' at {}", idx)),
+                };
                 ir[open] = Instr::Jz(idx);
                 ir[idx] = Instr::Jnz(open);
             }
+            Instr::Return => {
+                let open = match stack.pop() {
+                    Some(open) if matches!(ir[open], Instr::Define(_)) => open,
+                    _ => return Err(format!("unmatched ')' at {}", idx)),
+                };
+                ir[open] = Instr::Define(idx);
+            }
             _ => {}
         }
     }
 
-    if let Some(open) = stack.last() {
-        return Err(format!("unmatched '[' at {}", open));
+    if let Some(&open) = stack.last() {
+        let op = if matches!(ir[open], Instr::Define(_)) { '(' } else { '[' };
+        return Err(format!("unmatched '{}' at {}", op, open));
     }
 
     Ok(())
 }
 
-fn merge_linear(ir: &mut Vec<Instr>) {
+fn merge_linear(ir: &mut Vec<Instr>, positions: &mut Vec<usize>) {
     let mut out = Vec::with_capacity(ir.len());
+    let mut out_pos = Vec::with_capacity(ir.len());
     let mut i = 0;
     while i < ir.len() {
+        let pos = positions[i];
         match &ir[i] {
             Instr::Add(_) => {
                 let mut acc: i64 = 0;
@@ -135,6 +195,7 @@ fn merge_linear(ir: &mut Vec<Instr>) {
                     }
                 }
                 push_add(&mut out, acc);
+                out_pos.resize(out.len(), pos);
             }
             Instr::Move(_) => {
                 let mut acc: i64 = 0;
@@ -147,18 +208,39 @@ fn merge_linear(ir: &mut Vec<Instr>) {
                         _ => break,
                     }
                 }
-                push_move(&mut out, acc);
+                push_move(&mut out, acc, Instr::Move);
+                out_pos.resize(out.len(), pos);
+            }
+            Instr::Vertical(_) => {
+                let mut acc: i64 = 0;
+                while i < ir.len() {
+                    match &ir[i] {
+                        Instr::Vertical(delta) => {
+                            acc += *delta as i64;
+                            i += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                push_move(&mut out, acc, Instr::Vertical);
+                out_pos.resize(out.len(), pos);
             }
             _ => {
                 out.push(ir[i].clone());
+                out_pos.push(pos);
                 i += 1;
             }
         }
     }
     *ir = out;
+    *positions = out_pos;
 }
 
-fn try_addmul_loop(ir: &[Instr], start: usize) -> Option<(usize, Vec<(i32, i32)>)> {
+fn try_addmul_loop(
+    ir: &[Instr],
+    start: usize,
+    topology: &Topology,
+) -> Option<(usize, Vec<(i32, i32)>)> {
     let target = match ir.get(start)? {
         Instr::Jz(target) => *target,
         _ => return None,
@@ -208,6 +290,10 @@ fn try_addmul_loop(ir: &[Instr], start: usize) -> Option<(usize, Vec<(i32, i32)>
         edits.push((offset, factor));
     }
     edits.sort_by_key(|(offset, _)| *offset);
+    let offsets: Vec<i32> = edits.iter().map(|(offset, _)| *offset).collect();
+    if !topology.distinct(&offsets) {
+        return None;
+    }
     Some((target + 1, edits))
 }
 
@@ -228,7 +314,7 @@ fn push_add(out: &mut Vec<Instr>, mut acc: i64) {
     }
 }
 
-fn push_move(out: &mut Vec<Instr>, mut acc: i64) {
+fn push_move(out: &mut Vec<Instr>, mut acc: i64, instr: fn(i32) -> Instr) {
     if acc == 0 {
         return;
     }
@@ -240,7 +326,7 @@ fn push_move(out: &mut Vec<Instr>, mut acc: i64) {
             let min = i32::MIN as i64;
             if acc < min { min } else { acc }
         };
-        out.push(Instr::Move(chunk as i32));
+        out.push(instr(chunk as i32));
         acc -= chunk;
     }
 }
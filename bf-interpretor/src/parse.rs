@@ -1,7 +1,43 @@
 pub fn filter_ops(bytes: &[u8]) -> Vec<u8> {
+    filter_ops_with(bytes, &[])
+}
+
+pub fn op_offsets(bytes: &[u8]) -> Vec<usize> {
+    op_offsets_with(bytes, &[])
+}
+
+pub fn filter_ops_with(bytes: &[u8], extensions: &[u8]) -> Vec<u8> {
     bytes
         .iter()
         .copied()
-        .filter(|b| matches!(b, b'<' | b'>' | b'+' | b'-' | b'.' | b',' | b'[' | b']'))
+        .filter(|b| is_op(*b) || extensions.contains(b))
+        .collect()
+}
+
+pub fn op_offsets_with(bytes: &[u8], extensions: &[u8]) -> Vec<usize> {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| is_op(**b) || extensions.contains(b))
+        .map(|(idx, _)| idx)
         .collect()
 }
+
+pub fn line_col(bytes: &[u8], offset: usize) -> (usize, usize) {
+    let end = offset.min(bytes.len());
+    let mut line = 1;
+    let mut col = 1;
+    for &b in &bytes[..end] {
+        if b == b'\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    (line, col)
+}
+
+pub fn is_op(b: u8) -> bool {
+    matches!(b, b'<' | b'>' | b'+' | b'-' | b'.' | b',' | b'[' | b']')
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+const HEADER: &str = "bfpp-source-map 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    offset: usize,
+    file: usize,
+    line: usize,
+    col: usize,
+    advancing: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<String>,
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    pub fn build(files: Vec<String>, origins: &[(usize, usize, usize)]) -> SourceMap {
+        let mut segments: Vec<Segment> = Vec::new();
+        for (offset, &(file, line, col)) in origins.iter().enumerate() {
+            if let Some(seg) = segments.last_mut() {
+                let same_line = seg.file == file && seg.line == line;
+                let advanced = same_line && col == seg.col + (offset - seg.offset);
+                let fixed = same_line && col == seg.col;
+                match seg.advancing {
+                    None if advanced => {
+                        seg.advancing = Some(true);
+                        continue;
+                    }
+                    None if fixed => {
+                        seg.advancing = Some(false);
+                        continue;
+                    }
+                    Some(true) if advanced => continue,
+                    Some(false) if fixed => continue,
+                    _ => {}
+                }
+            }
+            segments.push(Segment {
+                offset,
+                file,
+                line,
+                col,
+                advancing: None,
+            });
+        }
+        for seg in &mut segments {
+            seg.advancing.get_or_insert(false);
+        }
+        SourceMap { files, segments }
+    }
+
+    pub fn lookup(&self, offset: usize) -> Option<Location> {
+        let idx = self.segments.partition_point(|seg| seg.offset <= offset);
+        let seg = self.segments.get(idx.checked_sub(1)?)?;
+        let col = if seg.advancing == Some(true) {
+            seg.col + (offset - seg.offset)
+        } else {
+            seg.col
+        };
+        Some(Location {
+            file: self.files.get(seg.file)?.clone(),
+            line: seg.line,
+            col,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<SourceMap, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read '{}': {}", path, e))?;
+        SourceMap::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => return Err("source map error: missing header".to_string()),
+        }
+        let mut map = SourceMap::default();
+        for (idx, line) in lines {
+            let error = || format!("source map error: invalid entry at line {}", idx + 1);
+            if let Some(rest) = line.strip_prefix("file ") {
+                let (index, path) = rest.split_once(' ').ok_or_else(error)?;
+                if index.parse::<usize>().ok() != Some(map.files.len()) {
+                    return Err(error());
+                }
+                map.files.push(path.to_string());
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 5 {
+                return Err(error());
+            }
+            let number = |field: &str| field.parse::<usize>().map_err(|_| error());
+            let segment = Segment {
+                offset: number(fields[0])?,
+                file: number(fields[1])?,
+                line: number(fields[2])?,
+                col: number(fields[3])?,
+                advancing: match fields[4] {
+                    "c" => Some(true),
+                    "g" => Some(false),
+                    _ => return Err(error()),
+                },
+            };
+            if segment.file >= map.files.len()
+                || map.segments.last().is_some_and(|last| last.offset >= segment.offset)
+            {
+                return Err(error());
+            }
+            map.segments.push(segment);
+        }
+        Ok(map)
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for (idx, file) in self.files.iter().enumerate() {
+            writeln!(f, "file {} {}", idx, file)?;
+        }
+        for seg in &self.segments {
+            let kind = if seg.advancing == Some(true) { "c" } else { "g" };
+            writeln!(
+                f,
+                "{} {} {} {} {}",
+                seg.offset, seg.file, seg.line, seg.col, kind
+            )?;
+        }
+        Ok(())
+    }
+}
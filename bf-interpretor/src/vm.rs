@@ -1,10 +1,136 @@
+use crate::framebuffer::Framebuffer;
 use crate::io::{Debug, Input, Output};
 use crate::ir::Instr;
+use std::collections::{BTreeSet, VecDeque};
 use std::io::{Read, Write};
 
+const MAX_CALL_DEPTH: usize = 1 << 16;
+const MAX_THREADS: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Change,
+    Equals(u8),
+    Range(u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watch {
+    pub cell: usize,
+    pub kind: WatchKind,
+}
+
+impl Watch {
+    fn triggers(&self, old: u8, new: u8) -> bool {
+        match self.kind {
+            WatchKind::Write => true,
+            WatchKind::Change => old != new,
+            WatchKind::Equals(value) => new == value && old != value,
+            WatchKind::Range(lo, hi) => {
+                let inside = |v: u8| v >= lo && v <= hi;
+                inside(new) && !inside(old)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watch: Watch,
+    pub old: u8,
+    pub new: u8,
+    pub ip: usize,
+    pub step: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Halt {
+    Finished,
+    Stepped,
+    Breakpoint(usize),
+    Watch(WatchHit),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+    #[default]
+    Linear,
+    Grid { width: usize },
+}
+
+impl Topology {
+    pub fn grid(width: usize) -> Result<Topology, String> {
+        if width == 0 || i32::try_from(width).is_err() {
+            return Err(format!("invalid grid width {}", width));
+        }
+        Ok(Topology::Grid { width })
+    }
+
+    pub fn is_grid(&self) -> bool {
+        matches!(self, Topology::Grid { .. })
+    }
+
+    pub fn distinct(&self, offsets: &[i32]) -> bool {
+        let width = match self {
+            Topology::Linear => return true,
+            Topology::Grid { width } => *width as i64,
+        };
+        let mut columns: Vec<i64> =
+            offsets.iter().map(|&offset| (offset as i64).rem_euclid(width)).collect();
+        columns.push(0);
+        columns.sort_unstable();
+        columns.windows(2).all(|pair| pair[0] != pair[1])
+    }
+
+    pub fn locate(&self, idx: usize) -> (usize, usize) {
+        match self {
+            Topology::Linear => (idx, 0),
+            Topology::Grid { width } => (idx % width, idx / width),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    RoundRobin { quantum: u64 },
+    Random { quantum: u64, seed: u64 },
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::RoundRobin { quantum: 1 }
+    }
+}
+
+struct Thread {
+    id: usize,
+    pointer: usize,
+    ip: usize,
+    calls: Vec<usize>,
+}
+
 pub struct Vm {
     tape: Vec<u8>,
+    initial: Vec<u8>,
     pointer: usize,
+    ip: usize,
+    topology: Topology,
+    thread: usize,
+    waiting: VecDeque<Thread>,
+    spawned: usize,
+    schedule: Schedule,
+    slice: u64,
+    rng: u64,
+    steps: u64,
+    watches: Vec<Watch>,
+    hit: Option<(Watch, u8, u8)>,
+    breakpoints: BTreeSet<usize>,
+    labels: Vec<String>,
+    procedures: Vec<Option<usize>>,
+    calls: Vec<usize>,
+    storage: u8,
+    framebuffer: Option<Framebuffer>,
 }
 
 impl Vm {
@@ -12,19 +138,145 @@ impl Vm {
         if tape_size == 0 {
             return Err("tape size must be greater than 0".to_string());
         }
-        let mut tape = Vec::with_capacity(tape_size);
-        tape.resize(tape_size, 0);
-        Ok(Self { tape, pointer: 0 })
+        let tape = vec![0; tape_size];
+        Ok(Self {
+            tape,
+            initial: Vec::new(),
+            pointer: 0,
+            ip: 0,
+            topology: Topology::Linear,
+            thread: 0,
+            waiting: VecDeque::new(),
+            spawned: 0,
+            schedule: Schedule::default(),
+            slice: 1,
+            rng: 0,
+            steps: 0,
+            watches: Vec::new(),
+            hit: None,
+            breakpoints: BTreeSet::new(),
+            labels: Vec::new(),
+            procedures: vec![None; 256],
+            calls: Vec::new(),
+            storage: 0,
+            framebuffer: None,
+        })
     }
 
     pub fn pointer(&self) -> usize {
         self.pointer
     }
 
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn set_topology(&mut self, topology: Topology) {
+        if let Topology::Grid { width } = topology {
+            let rows = self.tape.len().div_ceil(width);
+            self.tape.resize(rows * width, 0);
+        }
+        self.topology = topology;
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    pub fn grid_rows(&self) -> usize {
+        let width = match self.topology {
+            Topology::Linear => return 1,
+            Topology::Grid { width } => width,
+        };
+        let last = self.tape.iter().rposition(|&cell| cell != 0).unwrap_or(0);
+        last.max(self.pointer) / width + 1
+    }
+
+    pub fn thread(&self) -> usize {
+        self.thread
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.waiting.len() + 1
+    }
+
+    pub fn set_schedule(&mut self, schedule: Schedule) -> Result<(), String> {
+        let quantum = match schedule {
+            Schedule::RoundRobin { quantum } | Schedule::Random { quantum, .. } => quantum,
+        };
+        if quantum == 0 {
+            return Err("scheduler quantum must be greater than 0".to_string());
+        }
+        self.schedule = schedule;
+        self.rng = Self::seed(schedule);
+        Ok(())
+    }
+
+    fn seed(schedule: Schedule) -> u64 {
+        let seed = match schedule {
+            Schedule::RoundRobin { .. } => 0,
+            Schedule::Random { seed, .. } => seed,
+        };
+        seed ^ 0x9E37_79B9_7F4A_7C15
+    }
+
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    pub fn set_breakpoints<I: IntoIterator<Item = usize>>(&mut self, ips: I) {
+        self.breakpoints = ips.into_iter().collect();
+    }
+
+    pub fn is_breakpoint(&self, ip: usize) -> bool {
+        self.breakpoints.contains(&ip)
+    }
+
+    pub fn set_trace_labels(&mut self, labels: Vec<String>) {
+        self.labels = labels;
+    }
+
     pub fn tape(&self) -> &[u8] {
         &self.tape
     }
 
+    pub fn set_initial_tape(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() > self.tape.len() {
+            return Err(format!(
+                "initial tape data ({} bytes) does not fit the tape ({} cells)",
+                data.len(),
+                self.tape.len()
+            ));
+        }
+        self.tape[..data.len()].copy_from_slice(data);
+        self.initial = data.to_vec();
+        Ok(())
+    }
+
+    pub fn set_framebuffer(&mut self, framebuffer: Framebuffer) {
+        self.framebuffer = Some(framebuffer);
+    }
+
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        self.framebuffer.as_ref()
+    }
+
+    pub fn storage(&self) -> u8 {
+        self.storage
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.calls.len()
+    }
+
     fn ensure_capacity(&mut self, required: usize) -> Result<(), String> {
         if required < self.tape.len() {
             return Ok(());
@@ -51,6 +303,10 @@ impl Vm {
         if delta == 0 {
             return Ok(());
         }
+        if let Topology::Grid { .. } = self.topology {
+            self.pointer = self.column(delta);
+            return Ok(());
+        }
         let delta_i64 = delta as i64;
         if delta_i64 > 0 {
             let shift = delta_i64 as usize;
@@ -72,10 +328,32 @@ impl Vm {
         Ok(())
     }
 
+    fn column(&self, delta: i32) -> usize {
+        let width = match self.topology {
+            Topology::Linear => return self.pointer,
+            Topology::Grid { width } => width,
+        };
+        let (x, y) = self.topology.locate(self.pointer);
+        y * width + (x as i64 + delta as i64).rem_euclid(width as i64) as usize
+    }
+
+    fn row(&self, delta: i32) -> usize {
+        let width = match self.topology {
+            Topology::Linear => return self.pointer,
+            Topology::Grid { width } => width,
+        };
+        let rows = self.tape.len() / width;
+        let (x, y) = self.topology.locate(self.pointer);
+        (y as i64 + delta as i64).rem_euclid(rows as i64) as usize * width + x
+    }
+
     fn offset_index(&mut self, offset: i32) -> Result<usize, String> {
         if offset == 0 {
             return Ok(self.pointer);
         }
+        if let Topology::Grid { .. } = self.topology {
+            return Ok(self.column(offset));
+        }
         if offset > 0 {
             let shift = offset as usize;
             let target = self
@@ -97,11 +375,126 @@ impl Vm {
 
     fn add_cell(&mut self, delta: i32) {
         let value = self.tape[self.pointer];
-        self.tape[self.pointer] = value.wrapping_add(delta as u8);
+        self.set_cell(self.pointer, value.wrapping_add(delta as u8));
+    }
+
+    fn set_cell(&mut self, idx: usize, value: u8) {
+        if self.watches.is_empty() {
+            self.tape[idx] = value;
+            return;
+        }
+        let old = self.tape[idx];
+        self.tape[idx] = value;
+        if self.hit.is_none() {
+            self.check_watches(idx, old, value);
+        }
+    }
+
+    fn check_watches(&mut self, idx: usize, old: u8, new: u8) {
+        for watch in &self.watches {
+            if watch.cell == idx && watch.triggers(old, new) {
+                self.hit = Some((*watch, old, new));
+                return;
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.tape.fill(0);
+        self.tape[..self.initial.len()].copy_from_slice(&self.initial);
+        self.pointer = 0;
+        self.storage = 0;
+        self.slice = 1;
+        self.rng = Self::seed(self.schedule);
+        if let Some(fb) = &mut self.framebuffer {
+            fb.rewind();
+        }
+        self.ip = 0;
+        self.steps = 0;
+        self.hit = None;
+        self.thread = 0;
+        self.spawned = 0;
+        self.waiting.clear();
+        self.calls.clear();
+        self.procedures.fill(None);
+    }
+
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn quantum(&mut self) -> u64 {
+        match self.schedule {
+            Schedule::RoundRobin { quantum } => quantum,
+            Schedule::Random { quantum, .. } => 1 + self.random() % quantum,
+        }
+    }
+
+    fn pick(&mut self, count: usize) -> usize {
+        match self.schedule {
+            Schedule::RoundRobin { .. } => 0,
+            Schedule::Random { .. } => (self.random() % count as u64) as usize,
+        }
+    }
+
+    fn switch_to(&mut self, idx: usize) {
+        let next = self.waiting.remove(idx).expect("thread index in range");
+        self.thread = next.id;
+        self.pointer = next.pointer;
+        self.ip = next.ip;
+        self.calls = next.calls;
+        self.slice = self.quantum();
+    }
+
+    fn preempt(&mut self) {
+        let idx = self.pick(self.waiting.len() + 1);
+        if idx == self.waiting.len() {
+            self.slice = self.quantum();
+            return;
+        }
+        self.waiting.push_back(Thread {
+            id: self.thread,
+            pointer: self.pointer,
+            ip: self.ip,
+            calls: std::mem::take(&mut self.calls),
+        });
+        self.switch_to(idx);
+    }
+
+    fn retire(&mut self) -> bool {
+        if self.waiting.is_empty() {
+            return false;
+        }
+        let idx = self.pick(self.waiting.len());
+        self.switch_to(idx);
+        true
+    }
+
+    fn fork(&mut self, ip: usize) -> Result<(), String> {
+        if self.waiting.len() + 1 >= MAX_THREADS {
+            return Err("runtime error: too many threads".to_string());
+        }
+        let child = self.offset_index(1)?;
+        self.set_cell(self.pointer, 0);
+        self.set_cell(child, 1);
+        self.spawned += 1;
+        if self.waiting.is_empty() {
+            self.slice = self.quantum() - 1;
+        }
+        self.waiting.push_back(Thread {
+            id: self.spawned,
+            pointer: child,
+            ip: ip + 1,
+            calls: self.calls.clone(),
+        });
+        Ok(())
     }
 
     fn wrap_cell(value: i64) -> u8 {
-        let wrapped = ((value % 256) + 256) % 256;
+        let wrapped = value.rem_euclid(256);
         wrapped as u8
     }
 
@@ -112,7 +505,7 @@ impl Vm {
         output: &mut Output<W>,
         debug: Option<&mut Debug<E>>,
         max_steps: Option<u64>,
-    ) -> Result<(), String>
+    ) -> Result<Halt, String>
     where
         R: Read,
         W: Write,
@@ -122,36 +515,114 @@ impl Vm {
     }
 
     pub fn run<R, W, E>(
+        &mut self,
+        ir: &[Instr],
+        input: &mut Input<R>,
+        output: &mut Output<W>,
+        debug: Option<&mut Debug<E>>,
+        max_steps: Option<u64>,
+    ) -> Result<Halt, String>
+    where
+        R: Read,
+        W: Write,
+        E: Write,
+    {
+        self.restart();
+        self.exec(ir, input, output, debug, max_steps, false)
+    }
+
+    pub fn resume<R, W, E>(
+        &mut self,
+        ir: &[Instr],
+        input: &mut Input<R>,
+        output: &mut Output<W>,
+        debug: Option<&mut Debug<E>>,
+        max_steps: Option<u64>,
+    ) -> Result<Halt, String>
+    where
+        R: Read,
+        W: Write,
+        E: Write,
+    {
+        self.exec(ir, input, output, debug, max_steps, false)
+    }
+
+    pub fn step<R, W, E>(
+        &mut self,
+        ir: &[Instr],
+        input: &mut Input<R>,
+        output: &mut Output<W>,
+        debug: Option<&mut Debug<E>>,
+        max_steps: Option<u64>,
+    ) -> Result<Halt, String>
+    where
+        R: Read,
+        W: Write,
+        E: Write,
+    {
+        self.exec(ir, input, output, debug, max_steps, true)
+    }
+
+    fn exec<R, W, E>(
         &mut self,
         ir: &[Instr],
         input: &mut Input<R>,
         output: &mut Output<W>,
         mut debug: Option<&mut Debug<E>>,
         max_steps: Option<u64>,
-    ) -> Result<(), String>
+        single_step: bool,
+    ) -> Result<Halt, String>
     where
         R: Read,
         W: Write,
         E: Write,
     {
-        let mut ip = 0usize;
-        let mut steps = 0u64;
+        let start = self.steps;
+        let tagged = debug.is_some() && ir.contains(&Instr::Fork);
+        let stops = single_step || !self.breakpoints.is_empty();
+        let watching = !self.watches.is_empty();
+        loop {
+            if self.ip >= ir.len() {
+                if self.retire() {
+                    continue;
+                }
+                break;
+            }
+            if self.slice == 0 && !self.waiting.is_empty() {
+                self.preempt();
+                continue;
+            }
+
+            if stops && self.steps > start {
+                if single_step {
+                    output.flush()?;
+                    return Ok(Halt::Stepped);
+                }
+                if self.breakpoints.contains(&self.ip) {
+                    output.flush()?;
+                    return Ok(Halt::Breakpoint(self.ip));
+                }
+            }
 
-        while ip < ir.len() {
             if let Some(limit) = max_steps {
-                if steps >= limit {
+                if self.steps >= limit {
                     return Err("runtime error: max steps exceeded".to_string());
                 }
             }
 
+            let ip = self.ip;
+            let step = self.steps;
             let instr = &ir[ip];
             if let Some(ref mut debug) = debug {
-                self.trace(debug, steps, ip, instr)?;
+                self.trace(debug, step, ip, instr, tagged)?;
             }
 
-            steps = steps
+            self.steps = step
                 .checked_add(1)
                 .ok_or_else(|| "runtime error: step counter overflow".to_string())?;
+            if !self.waiting.is_empty() {
+                self.slice -= 1;
+            }
 
             match instr {
                 Instr::Add(delta) => {
@@ -160,6 +631,9 @@ impl Vm {
                 Instr::Move(delta) => {
                     self.move_ptr(*delta)?;
                 }
+                Instr::Vertical(delta) => {
+                    self.pointer = self.row(*delta);
+                }
                 Instr::AddTo(offset, sign) => {
                     let value = self.tape[self.pointer];
                     if value != 0 {
@@ -170,8 +644,8 @@ impl Vm {
                         } else {
                             dest.wrapping_add(value)
                         };
-                        self.tape[target] = updated;
-                        self.tape[self.pointer] = 0;
+                        self.set_cell(target, updated);
+                        self.set_cell(self.pointer, 0);
                     }
                 }
                 Instr::AddMul(edits) => {
@@ -181,29 +655,48 @@ impl Vm {
                             let target = self.offset_index(*offset)?;
                             let dest = self.tape[target] as i64;
                             let delta = src * (*factor as i64);
-                            self.tape[target] = Self::wrap_cell(dest + delta);
+                            self.set_cell(target, Self::wrap_cell(dest + delta));
                         }
+                        self.set_cell(self.pointer, 0);
                     }
-                    self.tape[self.pointer] = 0;
                 }
                 Instr::Output => {
                     let byte = self.tape[self.pointer];
-                    output.write_byte(byte)?;
+                    match &mut self.framebuffer {
+                        Some(fb) if fb.present == Some(byte) => {
+                            fb.capture(&self.tape)?;
+                        }
+                        _ => output.write_byte(byte)?,
+                    }
+                }
+                Instr::Present => {
+                    if let Some(fb) = &mut self.framebuffer {
+                        fb.capture(&self.tape)?;
+                    }
                 }
                 Instr::Input => {
                     let value = input.read_byte()?;
-                    self.tape[self.pointer] = value;
+                    self.set_cell(self.pointer, value);
                 }
                 Instr::SetZero => {
-                    self.tape[self.pointer] = 0;
+                    if self.tape[self.pointer] != 0 {
+                        self.set_cell(self.pointer, 0);
+                    }
                 }
                 Instr::Scan(dir) => {
                     if *dir == 0 {
                         return Err("runtime error: scan direction zero".to_string());
                     }
-                    let step = if *dir > 0 { 1 } else { -1 };
                     while self.tape[self.pointer] != 0 {
-                        self.move_ptr(step)?;
+                        self.move_ptr(*dir)?;
+                    }
+                }
+                Instr::ScanVertical(dir) => {
+                    if *dir == 0 {
+                        return Err("runtime error: scan direction zero".to_string());
+                    }
+                    while self.tape[self.pointer] != 0 {
+                        self.pointer = self.row(*dir);
                     }
                 }
                 Instr::Jz(target) => {
@@ -211,7 +704,7 @@ impl Vm {
                         if *target >= ir.len() {
                             return Err("runtime error: jump target out of range".to_string());
                         }
-                        ip = (*target)
+                        self.ip = (*target)
                             .checked_add(1)
                             .ok_or_else(|| "runtime error: instruction pointer overflow".to_string())?;
                         continue;
@@ -222,19 +715,90 @@ impl Vm {
                         if *target >= ir.len() {
                             return Err("runtime error: jump target out of range".to_string());
                         }
-                        ip = *target;
+                        self.ip = *target;
                         continue;
                     }
                 }
+                Instr::Define(end) => {
+                    if *end >= ir.len() {
+                        return Err("runtime error: jump target out of range".to_string());
+                    }
+                    self.procedures[self.tape[self.pointer] as usize] = Some(ip + 1);
+                    self.ip = end + 1;
+                    continue;
+                }
+                Instr::Call => {
+                    let id = self.tape[self.pointer];
+                    let start = self.procedures[id as usize]
+                        .ok_or_else(|| format!("runtime error: undefined procedure {}", id))?;
+                    if self.calls.len() >= MAX_CALL_DEPTH {
+                        return Err("runtime error: call stack overflow".to_string());
+                    }
+                    self.calls.push(ip + 1);
+                    self.ip = start;
+                    continue;
+                }
+                Instr::Fork => {
+                    self.fork(ip)?;
+                }
+                Instr::End => {
+                    self.waiting.clear();
+                    self.ip = ir.len();
+                    continue;
+                }
+                Instr::Store => {
+                    self.storage = self.tape[self.pointer];
+                }
+                Instr::Load => {
+                    self.set_cell(self.pointer, self.storage);
+                }
+                Instr::Shl => {
+                    self.set_cell(self.pointer, self.tape[self.pointer] << 1);
+                }
+                Instr::Shr => {
+                    self.set_cell(self.pointer, self.tape[self.pointer] >> 1);
+                }
+                Instr::Not => {
+                    self.set_cell(self.pointer, !self.tape[self.pointer]);
+                }
+                Instr::Xor => {
+                    self.set_cell(self.pointer, self.tape[self.pointer] ^ self.storage);
+                }
+                Instr::And => {
+                    self.set_cell(self.pointer, self.tape[self.pointer] & self.storage);
+                }
+                Instr::Or => {
+                    self.set_cell(self.pointer, self.tape[self.pointer] | self.storage);
+                }
+                Instr::Return => {
+                    self.ip = self
+                        .calls
+                        .pop()
+                        .ok_or_else(|| "runtime error: return outside procedure".to_string())?;
+                    continue;
+                }
             }
 
-            ip = ip
+            self.ip = ip
                 .checked_add(1)
                 .ok_or_else(|| "runtime error: instruction pointer overflow".to_string())?;
+
+            if watching {
+                if let Some((watch, old, new)) = self.hit.take() {
+                    output.flush()?;
+                    return Ok(Halt::Watch(WatchHit {
+                        watch,
+                        old,
+                        new,
+                        ip,
+                        step,
+                    }));
+                }
+            }
         }
 
         output.flush()?;
-        Ok(())
+        Ok(Halt::Finished)
     }
 
     fn trace<E: Write>(
@@ -243,31 +807,60 @@ impl Vm {
         steps: u64,
         ip: usize,
         instr: &Instr,
+        tagged: bool,
     ) -> Result<(), String> {
         let cell = self.tape[self.pointer];
-        debug.write_fmt(format_args!(
-            "step={} ip={} ptr={} cell={} ",
-            steps, ip, self.pointer, cell
-        ))?;
+        debug.write_fmt(format_args!("step={} ", steps))?;
+        if tagged {
+            debug.write_fmt(format_args!("thread={} ", self.thread))?;
+        }
+        match self.topology {
+            Topology::Linear => debug.write_fmt(format_args!("ip={} ptr={} ", ip, self.pointer))?,
+            Topology::Grid { .. } => {
+                let (x, y) = self.topology.locate(self.pointer);
+                debug.write_fmt(format_args!("ip={} ptr={},{} ", ip, x, y))?
+            }
+        }
+        debug.write_fmt(format_args!("cell={} ", cell))?;
         match instr {
-            Instr::Add(delta) => debug.write_fmt(format_args!("Add {}\n", delta)),
-            Instr::Move(delta) => debug.write_fmt(format_args!("Move {}\n", delta)),
+            Instr::Add(delta) => debug.write_fmt(format_args!("Add {}", delta)),
+            Instr::Move(delta) => debug.write_fmt(format_args!("Move {}", delta)),
+            Instr::Vertical(delta) => debug.write_fmt(format_args!("Vertical {}", delta)),
             Instr::AddTo(offset, sign) => {
-                debug.write_fmt(format_args!("AddTo {} {}\n", offset, sign))
+                debug.write_fmt(format_args!("AddTo {} {}", offset, sign))
             }
             Instr::AddMul(edits) => {
                 debug.write_fmt(format_args!("AddMul"))?;
                 for (offset, factor) in edits {
                     debug.write_fmt(format_args!(" ({},{})", offset, factor))?;
                 }
-                debug.write_fmt(format_args!("\n"))
+                Ok(())
             }
-            Instr::Output => debug.write_fmt(format_args!("Output\n")),
-            Instr::Input => debug.write_fmt(format_args!("Input\n")),
-            Instr::Jz(target) => debug.write_fmt(format_args!("Jz {}\n", target)),
-            Instr::Jnz(target) => debug.write_fmt(format_args!("Jnz {}\n", target)),
-            Instr::SetZero => debug.write_fmt(format_args!("SetZero\n")),
-            Instr::Scan(dir) => debug.write_fmt(format_args!("Scan {}\n", dir)),
+            Instr::Output => debug.write_fmt(format_args!("Output")),
+            Instr::Input => debug.write_fmt(format_args!("Input")),
+            Instr::Jz(target) => debug.write_fmt(format_args!("Jz {}", target)),
+            Instr::Jnz(target) => debug.write_fmt(format_args!("Jnz {}", target)),
+            Instr::SetZero => debug.write_fmt(format_args!("SetZero")),
+            Instr::Scan(dir) => debug.write_fmt(format_args!("Scan {}", dir)),
+            Instr::ScanVertical(dir) => debug.write_fmt(format_args!("ScanVertical {}", dir)),
+            Instr::Define(end) => debug.write_fmt(format_args!("Define {}", end)),
+            Instr::Return => debug.write_fmt(format_args!("Return")),
+            Instr::Call => debug.write_fmt(format_args!("Call")),
+            Instr::Fork => debug.write_fmt(format_args!("Fork")),
+            Instr::Present => debug.write_fmt(format_args!("Present")),
+            Instr::End => debug.write_fmt(format_args!("End")),
+            Instr::Store => debug.write_fmt(format_args!("Store")),
+            Instr::Load => debug.write_fmt(format_args!("Load")),
+            Instr::Shl => debug.write_fmt(format_args!("Shl")),
+            Instr::Shr => debug.write_fmt(format_args!("Shr")),
+            Instr::Not => debug.write_fmt(format_args!("Not")),
+            Instr::Xor => debug.write_fmt(format_args!("Xor")),
+            Instr::And => debug.write_fmt(format_args!("And")),
+            Instr::Or => debug.write_fmt(format_args!("Or")),
+        }?;
+        match self.labels.get(ip) {
+            Some(label) => debug.write_fmt(format_args!(" at {}\n", label)),
+            None => debug.write_fmt(format_args!("\n")),
         }
     }
 }
@@ -1,5 +1,5 @@
 use bf::io::Debug;
-use bf::{brackets, io, ir, opt, parse, vm};
+use bf::{brackets, format, io, ir, opt, parse, vm};
 use std::fmt::Write as FmtWrite;
 use std::io::sink;
 use std::io::Cursor;
@@ -130,7 +130,7 @@ fn run_ir_pipeline(program: &str, optimize: bool) -> Outcome {
     let result = machine.run_ir(&ir, &mut input, &mut output, Some(&mut dbg), Some(MAX_STEPS));
     let output_bytes = output.into_inner().expect("output buffer flush failed");
     let termination = match result {
-        Ok(()) => Termination::Ok,
+        Ok(_) => Termination::Ok,
         Err(err) => classify_error(&err),
     };
 
@@ -183,6 +183,7 @@ fn ensure_capacity(tape: &mut Vec<u8>, required: usize) -> Result<(), String> {
     Ok(())
 }
 
+#[allow(clippy::collapsible_match)]
 fn run_reference(program: &str) -> Outcome {
     let ops = parse::filter_ops(program.as_bytes());
     let jumps = match build_jumps(&ops) {
@@ -333,3 +334,102 @@ fn addmul_emitted_for_linear_loops() {
         assert!(has_addmul, "expected AddMul in optimized IR for '{}'", program);
     }
 }
+
+#[test]
+fn minify_preserves_behaviour() {
+    let cases = [
+        ("cancel_moves", "+>+<>.<."),
+        ("cancel_adds", "+++-+-.>+-."),
+        ("dead_after_loop", "++[->+<][>.<]>."),
+        ("dead_at_start", "[+.]++."),
+        ("cancel_exposes_dead", "+[-]+-[+.]."),
+        ("nested_loops", "++[>++[>+<-]<-]>>."),
+        ("move_add_file", include_str!("../programs/tests/move_add.bf")),
+        ("scan_right_file", include_str!("../programs/tests/scan_right.bf")),
+        ("loop_small_file", include_str!("../programs/tests/loop_small.bf")),
+        ("step_over_file", include_str!("../programs/tests/step_over.bf")),
+    ];
+
+    for (case, program) in cases {
+        let minified = format::minify(program.as_bytes()).expect("minify failed");
+        let reference = run_reference(program);
+        let plain = run_reference(&minified);
+        let optimized = run_ir_pipeline(&minified, true);
+        compare_outcomes(case, "reference", &reference, "minified", &plain, program);
+        compare_outcomes(case, "reference", &reference, "minified-opt", &optimized, program);
+    }
+}
+
+#[test]
+fn minify_keeps_moves_past_the_left_edge() {
+    let program = "+.<>.";
+    let minified = format::minify(program.as_bytes()).expect("minify failed");
+    let reference = run_reference(program);
+    let plain = run_reference(&minified);
+    compare_outcomes("left_edge", "reference", &reference, "minified", &plain, program);
+}
+
+#[test]
+fn format_preserves_behaviour() {
+    let cases = [
+        ("nested_loops", "++[>++[>+<-]<-]>>."),
+        ("commented", "set A\n+++++[>+++++++++++++<-]>. print"),
+        ("scan_left_file", include_str!("../programs/tests/scan_left.bf")),
+    ];
+
+    for (case, program) in cases {
+        let formatted = format::format(program.as_bytes()).expect("format failed");
+        let reference = run_reference(program);
+        let outcome = run_reference(&formatted);
+        compare_outcomes(case, "reference", &reference, "formatted", &outcome, program);
+    }
+}
+
+fn corpus_files() -> Vec<std::path::PathBuf> {
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("programs");
+    let mut files = Vec::new();
+    for dir in ["tests", "stress"] {
+        let entries = std::fs::read_dir(root.join(dir)).expect("failed to read corpus dir");
+        for entry in entries {
+            let path = entry.expect("failed to read corpus entry").path();
+            if path.extension().and_then(|e| e.to_str()) == Some("bf") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn emit_bf_round_trips_corpus() {
+    let files = corpus_files();
+    assert!(!files.is_empty());
+    for path in files {
+        let source = std::fs::read_to_string(&path).expect("failed to read corpus file");
+        let ir = build_ir(&source, true).expect("failed to build IR");
+        let emitted = ir::emit_bf(&ir).expect("failed to emit BF");
+        let reparsed = build_ir(&emitted, true).expect("failed to rebuild IR");
+        assert_eq!(ir, reparsed, "round trip changed IR for {}", path.display());
+    }
+}
+
+#[test]
+fn emit_bf_preserves_behaviour() {
+    let cases = [
+        ("move_add_multi", "+++[->+>+<<]>>."),
+        ("move_add_multiplier", "+++[->++<]>."),
+        ("addmul_tail_decrement", "+++[>+<-]>."),
+        ("move_add_negative", ">+++[<+>-]<."),
+        ("scan_right", "+>+>+<<[>]<."),
+        ("empty_addmul", "++[->+-<]."),
+    ];
+
+    for (case, program) in cases {
+        let ir = build_ir(program, true).expect("failed to build IR");
+        let emitted = ir::emit_bf(&ir).expect("failed to emit BF");
+        let reference = run_reference(program);
+        let outcome = run_reference(&emitted);
+        compare_outcomes(case, "reference", &reference, "emitted", &outcome, program);
+    }
+}
@@ -1,6 +1,6 @@
 use std::env;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Output};
 
 fn bf_path() -> PathBuf {
     let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -13,13 +13,56 @@ fn bf_path() -> PathBuf {
     path
 }
 
-fn run_bf(args: &[&str]) -> std::process::Output {
+fn run_bf(args: &[&str]) -> Output {
     Command::new(bf_path())
         .args(args)
         .output()
         .expect("failed to run bf")
 }
 
+fn stdout_of(out: Output) -> Vec<u8> {
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    out.stdout
+}
+
+fn stderr_of(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+struct Fixture {
+    dir: PathBuf,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Fixture {
+        let dir = env::temp_dir().join(format!("bf-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Fixture { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    fn run(&self, source: &str, flags: &[&str]) -> Output {
+        let program = self.path("program.b");
+        std::fs::write(&program, source).unwrap();
+        let mut args = flags.to_vec();
+        args.push(&program);
+        run_bf(&args)
+    }
+
+    fn read(&self, name: &str) -> Vec<u8> {
+        std::fs::read(self.dir.join(name)).unwrap()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
 #[test]
 fn pure_a_outputs_byte() {
     let out = run_bf(&["programs/tests/pure_A.bf"]);
@@ -51,3 +94,137 @@ fn max_steps_exits_nonzero() {
     let stderr = String::from_utf8_lossy(&out.stderr);
     assert!(stderr.contains("max steps"));
 }
+
+#[test]
+fn watch_reports_location() {
+    let out = run_bf(&["--watch", "cell=1:65", "programs/tests/move_add.bf"]);
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("watchpoint: cell=1"), "{}", stderr);
+    assert!(stderr.contains("move_add.bf:2:1"), "{}", stderr);
+}
+
+#[test]
+fn dap_answers_initialize() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let body = r#"{"seq":1,"type":"request","command":"initialize"}"#;
+    let mut child = Command::new(bf_path())
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run bf dap");
+    let mut stdin = child.stdin.take().unwrap();
+    write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    drop(stdin);
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("\"command\":\"initialize\""), "{}", stdout);
+    assert!(stdout.contains("\"event\":\"initialized\""), "{}", stdout);
+}
+
+#[test]
+fn dialect_translate_and_run() {
+    let fixture = Fixture::new("dialect");
+    let ook = fixture.path("program.ook");
+    let out = run_bf(&["translate", "programs/tests/loop_small.bf", "--to", "ook", "-o", &ook]);
+    stdout_of(out);
+    assert_eq!(stdout_of(run_bf(&["--dialect", "ook", &ook])), b"B");
+
+    let out = stdout_of(run_bf(&["translate", &ook, "--from", "ook", "--to", "bf"]));
+    assert_eq!(String::from_utf8_lossy(&out).trim(), "++++++[>++++++++++<-]>++++++.");
+
+    let out = run_bf(&["--dialect", "klingon", &ook]);
+    assert!(!out.status.success());
+    assert!(stderr_of(&out).contains("unknown dialect 'klingon'"));
+}
+
+#[test]
+fn pbrain_procedures_run_behind_dialect_flag() {
+    let fixture = Fixture::new("pbrain");
+    let program = "+(>++++++++[>++++++++<-]>+.[-]<<)::";
+    assert_eq!(stdout_of(fixture.run(program, &["--dialect", "pbrain"])), b"AA");
+    assert_eq!(fixture.run(program, &[]).stdout, b"A");
+
+    let out = fixture.run("+:", &["--dialect", "pbrain"]);
+    assert!(!out.status.success());
+    assert!(stderr_of(&out).contains("undefined procedure 1"));
+}
+
+#[test]
+fn brainfork_threads_follow_the_schedule() {
+    let fixture = Fixture::new("brainfork");
+    assert_eq!(stdout_of(fixture.run("Y.", &["--dialect", "brainfork"])), vec![1, 0]);
+    let out = fixture.run("Y.", &["--dialect", "brainfork", "--quantum", "2"]);
+    assert_eq!(stdout_of(out), vec![0, 1]);
+
+    let seeded = stdout_of(fixture.run("Y.", &["--dialect", "brainfork", "--seed", "42"]));
+    let again = stdout_of(fixture.run("Y.", &["--dialect", "brainfork", "--seed=42"]));
+    assert_eq!(seeded, again);
+
+    let out = fixture.run("Y.", &["--dialect", "brainfork", "--trace"]);
+    let trace = stderr_of(&out);
+    assert!(trace.contains("step=1 thread=1 ip=1 ptr=1 cell=1 Output"), "{}", trace);
+}
+
+#[test]
+fn extended_type_one_runs_behind_dialect_flag() {
+    let fixture = Fixture::new("extended1");
+    let out = fixture.run("++++++++$>!{{|.@", &["--dialect", "extended1"]);
+    assert_eq!(stdout_of(out), b"(");
+    let out = fixture.run("+.>.@AB data [ unmatched", &["--dialect", "extended1"]);
+    assert_eq!(stdout_of(out), b"BB");
+}
+
+#[test]
+fn grid_dialect_dumps_the_final_grid() {
+    let fixture = Fixture::new("grid");
+    let image = fixture.path("grid.pgm");
+    let flags = ["--dialect", "2d", "--grid", "4", "--grid-dump", &image];
+    stdout_of(fixture.run("+v++>+++^>++++[v]", &flags));
+    assert_eq!(fixture.read("grid.pgm"), b"P5\n4 2\n255\n\x01\x00\x04\x00\x02\x03\x00\x00");
+
+    stdout_of(fixture.run("^+>>>>>+", &[&flags[..], &["--tape", "8"]].concat()));
+    assert_eq!(fixture.read("grid.pgm"), b"P5\n4 2\n255\n\x00\x00\x00\x00\x01\x01\x00\x00");
+
+    let out = fixture.run("+", &["--grid-dump", &image]);
+    assert!(stderr_of(&out).contains("--grid-dump needs --grid"));
+    let out = fixture.run("+", &["--grid", "4294967296"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr_of(&out).contains("invalid grid width 4294967296"));
+}
+
+#[test]
+fn framebuffer_captures_frames_on_present() {
+    let fixture = Fixture::new("framebuffer");
+    let frames = fixture.path("frames");
+    let flags = ["--dialect", "framebuffer", "--framebuffer", "2x1x1", "--frames", &frames];
+    let out = fixture.run("++>+#>-.+++.", &[&flags[..], &["--present", "255"]].concat());
+    assert_eq!(stdout_of(out), vec![2]);
+    let mut names: Vec<_> = std::fs::read_dir(&frames)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["frame-00000.pgm", "frame-00001.pgm"]);
+    assert_eq!(fixture.read("frames/frame-00001.pgm"), b"P5\n2 1\n255\n\x02\x01");
+
+    let out = fixture.run("++>+#>-.+++.", &["--present", "255"]);
+    assert!(!out.status.success());
+    assert!(stderr_of(&out).contains("need --framebuffer"));
+}
+
+#[test]
+fn framebuffer_combines_with_the_grid_dialect() {
+    let fixture = Fixture::new("grid-framebuffer");
+    let frames = fixture.path("frames");
+    let flags = ["--dialect", "2d+framebuffer", "--grid", "2", "--framebuffer", "2x2x1"];
+    stdout_of(fixture.run("+v++>+++#", &[&flags[..], &["--frames", &frames]].concat()));
+    assert_eq!(fixture.read("frames/frame-00000.pgm"), b"P5\n2 2\n255\n\x01\x00\x02\x03");
+
+    let help = stdout_of(run_bf(&["--help"]));
+    assert!(String::from_utf8_lossy(&help).contains("that byte is swallowed"));
+}
@@ -0,0 +1,791 @@
+use bf::io;
+use bf::io::Debug;
+use bf::srcmap::SourceMap;
+use bf::vm::Vm;
+use std::env;
+use std::fs;
+use std::io::{sink, Cursor};
+use std::path::PathBuf;
+use std::process::Command;
+
+fn bfpp_path() -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("target");
+    path.push("debug");
+    path.push("bfpp");
+    if cfg!(windows) {
+        path.set_extension("exe");
+    }
+    path
+}
+
+fn bf_path() -> PathBuf {
+    let mut path = bfpp_path();
+    path.set_file_name(if cfg!(windows) { "bf.exe" } else { "bf" });
+    path
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let mut path = env::temp_dir();
+    path.push(format!("bfpp-test-{}-{}", std::process::id(), name));
+    path
+}
+
+fn preprocess_file(input: &str) -> Result<String, String> {
+    preprocess_with(input, &[], None)
+}
+
+fn preprocess_with(input: &str, args: &[&str], env_path: Option<&str>) -> Result<String, String> {
+    let output = temp_path(&format!("{}.bf", input.replace('/', "_")));
+    let mut command = Command::new(bfpp_path());
+    command.args([input, "-o", output.to_str().unwrap()]).args(args);
+    match env_path {
+        Some(value) => command.env("BFPP_PATH", value),
+        None => command.env_remove("BFPP_PATH"),
+    };
+    let out = command.output().expect("failed to run bfpp");
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).into_owned());
+    }
+    let text = fs::read_to_string(&output).expect("bfpp output missing");
+    let _ = fs::remove_file(&output);
+    Ok(text)
+}
+
+fn preprocess(name: &str, source: &str) -> Result<String, String> {
+    let input = temp_path(&format!("{}.bfpp", name));
+    fs::write(&input, source).expect("failed to write bfpp input");
+    let result = preprocess_file(input.to_str().unwrap());
+    let _ = fs::remove_file(&input);
+    result
+}
+
+fn execute(bf_source: &str) -> Vec<u8> {
+    let program = bf::compile(bf_source.as_bytes(), false).expect("compile failed");
+    let mut machine = Vm::with_capacity(64).unwrap();
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let mut dbg_sink = sink();
+    let mut dbg = Debug::new(&mut dbg_sink);
+    machine
+        .run(&program.ir, &mut input, &mut output, Some(&mut dbg), Some(1_000_000))
+        .unwrap();
+    output.into_inner().unwrap()
+}
+
+#[test]
+fn macro_expands_arguments() {
+    let src = "@macro emit(n) { @repeat $n + . [-] }\n@emit(65)\n@emit(66)\n";
+    let out = preprocess("expand", src).unwrap();
+    assert_eq!(execute(&out), b"AB");
+}
+
+#[test]
+fn nested_macros_expand() {
+    let src = "@macro add(n) { @repeat $n + }\n\
+               @macro twice(n) { @add($n) @add($n) }\n\
+               @twice(33).\n";
+    let out = preprocess("nested", src).unwrap();
+    assert_eq!(execute(&out), b"B");
+}
+
+#[test]
+fn macro_arguments_can_contain_input() {
+    let src = "@macro io(x) { $x }\n@io([,.])\n@io(\\,.)\n";
+    let out = preprocess("input_args", src).unwrap();
+    assert_eq!(bf::parse::filter_ops(out.as_bytes()), b"[,.],.".to_vec());
+    let src = "@macro io(x) { $x }\n@io(+, \\,)\n";
+    let err = preprocess("input_args_two", src).unwrap_err();
+    assert!(err.contains("macro 'io' expects 1 argument(s), got 2"), "{}", err);
+}
+
+#[test]
+fn memory_library_smoke() {
+    let out = preprocess_file("programs/tests/memory.smoke.bfpp").unwrap();
+    assert_eq!(execute(&out), b"A");
+}
+
+#[test]
+fn redefinition_reports_both_lines() {
+    let src = "@macro a() { + }\n\n@macro a() { - }\n";
+    let err = preprocess("redefine", src).unwrap_err();
+    assert!(err.contains("macro 'a' redefined (first defined on line 1)"), "{}", err);
+}
+
+#[test]
+fn wrong_arity_shows_expansion_trace() {
+    let src = "@macro b(x) { + }\n@macro a(x) { @b($x, 1) }\n\n@a(2)\n";
+    let err = preprocess("arity", src).unwrap_err();
+    let path = temp_path("arity.bfpp").display().to_string();
+    let expected = format!(
+        "{0}:2:15: macro error: macro 'b' expects 1 argument(s), got 2\n  \
+         in expansion of @a at {0}:4:1\n2 |",
+        path
+    );
+    assert!(err.contains(&expected), "{}", err);
+}
+
+#[test]
+fn recursive_macro_hits_depth_limit() {
+    let src = "@macro loop() { + @loop() }\n@loop()\n";
+    let err = preprocess("recursive", src).unwrap_err();
+    assert!(err.contains("depth limit (32) exceeded"), "{}", err);
+}
+
+#[test]
+fn unknown_macro_is_error() {
+    let err = preprocess("unknown", "@missing()\n").unwrap_err();
+    assert!(err.contains("unknown macro 'missing'\n"), "{}", err);
+}
+
+#[test]
+fn named_cells_emit_pointer_moves() {
+    let src = "@var a\n@var b\n@var c\n\
+               @at c @repeat 66 +\n\
+               @goto a @repeat 65 + .\n\
+               @at c . @at b @repeat 67 + .\n";
+    let out = preprocess("cells", src).unwrap();
+    assert_eq!(execute(&out), b"ABC");
+    assert!(out.contains(">>"), "{}", out);
+}
+
+#[test]
+fn named_cells_survive_balanced_loops() {
+    let src = "@var count\n@var total\n\
+               @at count @repeat 5 +\n\
+               [ @at total @repeat 13 + @at count - ]\n\
+               @at total .\n";
+    let out = preprocess("balanced", src).unwrap();
+    assert_eq!(execute(&out), b"A");
+}
+
+#[test]
+fn unbalanced_loop_loses_position() {
+    let src = "@var a\n@var b\n\n+[>]\n@at b\n";
+    let err = preprocess("unbalanced", src).unwrap_err();
+    assert!(
+        err.contains("pointer position is not statically known (unbalanced loop on line 4)"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn named_cell_inside_unbalanced_loop_is_error() {
+    let src = "@var a\n@var b\n+[ @at b >]\n";
+    let err = preprocess("unbalanced_inner", src).unwrap_err();
+    assert!(err.contains("named cell used inside unbalanced loop opened on line 3"), "{}", err);
+}
+
+#[test]
+fn unknown_and_redeclared_cells_are_errors() {
+    let err = preprocess("unknown_cell", "@at nowhere\n").unwrap_err();
+    assert!(err.contains("unknown cell 'nowhere'\n"), "{}", err);
+    let err = preprocess("redeclared_cell", "@var a\n@var a\n").unwrap_err();
+    assert!(err.contains("cell 'a' redeclared\n"), "{}", err);
+}
+
+#[test]
+fn print_emits_string_with_escapes() {
+    let src = "+++ @print \"Hi #1 // \\\"ok\\\"\\t\\x41\\n\"\n";
+    let out = preprocess("print", src).unwrap();
+    assert_eq!(execute(&out), b"Hi #1 // \"ok\"\tA\n");
+}
+
+#[test]
+fn print_is_shorter_than_plain_deltas() {
+    let out = preprocess_file("programs/bfpp/hello_print.bfpp").unwrap();
+    assert_eq!(execute(&out), b"Hello, World!\n");
+    let plain: usize = b"Hello, World!\n"
+        .iter()
+        .scan(0u8, |prev, &b| {
+            let delta = b.wrapping_sub(*prev);
+            *prev = b;
+            Some(delta.min(0u8.wrapping_sub(delta)) as usize + 1)
+        })
+        .sum();
+    assert!(out.trim().len() < plain, "{} >= {}", out.trim().len(), plain);
+}
+
+#[test]
+fn set_assigns_constants() {
+    for value in [0u8, 1, 65, 128, 200, 255] {
+        let src = format!("@repeat 7 + @set {} .\n", value);
+        let out = preprocess(&format!("set{}", value), &src).unwrap();
+        assert_eq!(execute(&out), vec![value]);
+    }
+    let out = preprocess("set_short", "@set 100\n").unwrap();
+    assert!(out.trim().len() < 100, "{}", out);
+}
+
+#[test]
+fn print_and_set_errors() {
+    let err = preprocess("print_open", "@print \"abc\n").unwrap_err();
+    assert!(err.contains("unterminated string literal\n"), "{}", err);
+    let err = preprocess("set_range", "\n@set 300\n").unwrap_err();
+    assert!(err.contains("@set value 300 out of range 0..255\n"), "{}", err);
+}
+
+#[test]
+fn print_and_set_keep_the_right_neighbour_intact() {
+    let src = "@var a\n@var b\n@scratch t\n@at b @set 7\n@at a @set 100 @print \"hi\" @at b .\n";
+    let out = preprocess("neighbour", src).unwrap();
+    assert_eq!(execute(&out), b"hi\x07".to_vec());
+    let err = preprocess("neighbour_full", "@var a\n@var b\n@set 100\n").unwrap_err();
+    assert!(
+        err.contains("@set needs the cell right of the pointer as scratch, but it is 'b'"),
+        "{}",
+        err
+    );
+}
+
+fn control_flow(name: &str, body: &str, x: u8, y: u8) -> Vec<u8> {
+    let src = format!(
+        "@var x\n@var y\n@var out\n\
+         @scratch t0\n@scratch t1\n@scratch t2\n@scratch t3\n@scratch t4\n\
+         @at x @set {}\n@at y @set {}\n{}\n",
+        x, y, body
+    );
+    let out = preprocess(&format!("{}_{}_{}", name, x, y), &src).unwrap();
+    execute(&out)
+}
+
+#[test]
+fn if_runs_only_when_nonzero() {
+    let body = "@if x { @at out @set 84 . }\n@at x .";
+    assert_eq!(control_flow("if", body, 0, 0), vec![0]);
+    assert_eq!(control_flow("if", body, 1, 0), vec![84, 1]);
+    assert_eq!(control_flow("if", body, 200, 0), vec![84, 200]);
+}
+
+#[test]
+fn if_else_takes_one_branch() {
+    let body = "@if x { @at out @set 84 . } @else { @at out @set 70 . }\n@at x .";
+    assert_eq!(control_flow("ifelse", body, 0, 0), vec![70, 0]);
+    assert_eq!(control_flow("ifelse", body, 3, 0), vec![84, 3]);
+}
+
+#[test]
+fn nested_if_else_covers_all_branches() {
+    let body = "@if x {\n\
+                    @if y { @at out @set 65 . } @else { @at out @set 66 . }\n\
+                } @else {\n\
+                    @if y { @at out @set 67 . } @else { @at out @set 68 . }\n\
+                }\n\
+                @at x . @at y .";
+    assert_eq!(control_flow("nested", body, 1, 1), vec![65, 1, 1]);
+    assert_eq!(control_flow("nested", body, 1, 0), vec![66, 1, 0]);
+    assert_eq!(control_flow("nested", body, 0, 2), vec![67, 0, 2]);
+    assert_eq!(control_flow("nested", body, 0, 0), vec![68, 0, 0]);
+}
+
+#[test]
+fn while_loops_until_cell_is_zero() {
+    let body = "@while x { @at out + @at x - }\n@at out .";
+    assert_eq!(control_flow("while", body, 0, 0), vec![0]);
+    assert_eq!(control_flow("while", body, 4, 0), vec![4]);
+}
+
+#[test]
+fn times_repeats_without_consuming_counter() {
+    let body = "@times x { @times y { @at out + } }\n@at out . @at x . @at y .";
+    assert_eq!(control_flow("times", body, 0, 5), vec![0, 0, 5]);
+    assert_eq!(control_flow("times", body, 3, 0), vec![0, 3, 0]);
+    assert_eq!(control_flow("times", body, 3, 4), vec![12, 3, 4]);
+}
+
+#[test]
+fn control_flow_errors() {
+    let err = preprocess("no_scratch", "@var x\n@if x { }\n").unwrap_err();
+    assert!(
+        err.contains("@if needs 2 free scratch cell(s); declare more with @scratch"),
+        "{}",
+        err
+    );
+    let err = preprocess("stray_else", "@var x\n@else { }\n").unwrap_err();
+    assert!(err.contains("@else without matching @if\n"), "{}", err);
+    let err = preprocess("open_block", "@var x\n@while x {\n").unwrap_err();
+    assert!(err.contains("missing '}' for @while\n"), "{}", err);
+}
+
+#[test]
+fn source_map_points_into_included_files() {
+    let output = temp_path("smoke_map.bf");
+    let map_path = temp_path("smoke_map.map");
+    let status = Command::new(bfpp_path())
+        .args([
+            "programs/tests/memory.smoke.bfpp",
+            "-o",
+            output.to_str().unwrap(),
+            "-m",
+            map_path.to_str().unwrap(),
+        ])
+        .status()
+        .expect("failed to run bfpp");
+    assert!(status.success());
+    let text = fs::read_to_string(&output).unwrap();
+    let map = SourceMap::parse(&fs::read_to_string(&map_path).unwrap()).unwrap();
+
+    let plus = text.find('+').unwrap();
+    let location = map.lookup(plus).unwrap();
+    assert!(location.file.ends_with("lib/memory.bfpp"), "{}", location);
+    assert_eq!((location.line, location.col), (6, 28));
+
+    let dot = text.rfind('.').unwrap();
+    let location = map.lookup(dot).unwrap();
+    assert_eq!(location.file, "programs/tests/memory.smoke.bfpp");
+    assert_eq!((location.line, location.col), (6, 1));
+
+    let bf = Command::new(bf_path())
+        .args([output.to_str().unwrap(), "--source-map", map_path.to_str().unwrap()])
+        .output()
+        .expect("failed to run bf");
+    assert_eq!(bf.stdout, b"A");
+    let _ = fs::remove_file(&output);
+    let _ = fs::remove_file(&map_path);
+}
+
+#[test]
+fn bf_reports_original_location_of_runtime_error() {
+    let input = temp_path("underflow.bfpp");
+    let output = temp_path("underflow.bf");
+    let map_path = temp_path("underflow.map");
+    fs::write(&input, "@macro back() { < }\n+\n@back()\n").unwrap();
+    let status = Command::new(bfpp_path())
+        .args([
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-m",
+            map_path.to_str().unwrap(),
+        ])
+        .status()
+        .expect("failed to run bfpp");
+    assert!(status.success());
+    let bf = Command::new(bf_path())
+        .args([output.to_str().unwrap(), "--source-map", map_path.to_str().unwrap()])
+        .output()
+        .expect("failed to run bf");
+    assert!(!bf.status.success());
+    let stderr = String::from_utf8_lossy(&bf.stderr);
+    let expected = format!("runtime error: pointer underflow at {}:1:17", input.display());
+    assert!(stderr.contains(&expected), "{}", stderr);
+    for path in [&input, &output, &map_path] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+const STD_CELLS: &str = "@var a\n@var b\n@var r\n@var q\n@var x\n@var y\n@var e\n\
+                         @var t0\n@var t1\n@var t2\n@var t3\n@var t4\n@var t5\n\
+                         @scratch s0\n@scratch s1\n";
+
+fn run_std(name: &str, include: &str, a: u8, b: u8, body: &str) -> Vec<u8> {
+    let src = format!(
+        "#include <{}>\n{}@at a {} @at b {}\n{}\n",
+        include,
+        STD_CELLS,
+        "+".repeat(a as usize),
+        "+".repeat(b as usize),
+        body
+    );
+    let out = preprocess(&format!("std_{}_{}_{}", name, a, b), &src).unwrap();
+    execute(&out)
+}
+
+#[test]
+fn std_print_helpers() {
+    let out = run_std("print", "print.bfpp", 0, 0, "@at r @println(\"Hi, (you)\") @putc(33)");
+    assert_eq!(out, b"Hi, (you)\n!");
+}
+
+#[test]
+fn std_copy_and_move() {
+    let body = "@copy(a, b, t0) @at a . @at b . @move(b, r) @at b . @at r .";
+    assert_eq!(run_std("copy", "copy.bfpp", 42, 7, body), vec![42, 42, 0, 42]);
+}
+
+#[test]
+fn std_compare() {
+    let body = "@equal(a, b, r, t0, t1) @at r . @greater(a, b, r, x, y, t0) @at r . @at a . @at b .";
+    for (a, b) in [(0u8, 0u8), (3, 3), (5, 2), (2, 5), (0, 4), (255, 254)] {
+        let expected = vec![(a == b) as u8, (a > b) as u8, a, b];
+        assert_eq!(run_std("compare", "compare.bfpp", a, b, body), expected, "{} {}", a, b);
+    }
+}
+
+#[test]
+fn std_divmod() {
+    let body = "@divmod(a, b, q, r, x, e, t0, t1) @at q . @at r . @at a . @at b .";
+    for (n, d) in [(17u8, 5u8), (0, 3), (9, 3), (2, 7), (255, 10), (6, 1)] {
+        let expected = vec![n / d, n % d, n, d];
+        assert_eq!(run_std("divmod", "divmod.bfpp", n, d, body), expected, "{} {}", n, d);
+    }
+}
+
+#[test]
+fn std_decimal_print() {
+    let body = "@print_decimal(a, t0, t1, t2, t3, t4, x, e, y, t5) @at a .";
+    for value in [0u8, 7, 10, 42, 100, 109, 255] {
+        let mut expected = value.to_string().into_bytes();
+        expected.push(value);
+        assert_eq!(run_std("decimal", "decimal.bfpp", value, 0, body), expected);
+    }
+}
+
+#[test]
+fn library_search_path_order() {
+    let dir = temp_path("libdir");
+    let other = temp_path("libdir_env");
+    fs::create_dir_all(&dir).unwrap();
+    fs::create_dir_all(&other).unwrap();
+    fs::write(dir.join("seven.bfpp"), "@macro seven() { @repeat 55 + . }\n").unwrap();
+    fs::write(other.join("seven.bfpp"), "@macro seven() { @repeat 56 + . }\n").unwrap();
+    let input = temp_path("uses_seven.bfpp");
+    fs::write(&input, "#include <seven.bfpp>\n@seven()\n").unwrap();
+    let input = input.to_str().unwrap();
+
+    let flag = format!("-I{}", dir.display());
+    let out = preprocess_with(input, &[&flag], other.to_str()).unwrap();
+    assert_eq!(execute(&out), b"7");
+    let out = preprocess_with(input, &[], other.to_str()).unwrap();
+    assert_eq!(execute(&out), b"8");
+    let err = preprocess_with(input, &[], None).unwrap_err();
+    assert!(err.contains("library <seven.bfpp> not found in search path"), "{}", err);
+
+    let _ = fs::remove_dir_all(&dir);
+    let _ = fs::remove_dir_all(&other);
+    let _ = fs::remove_file(input);
+}
+
+#[test]
+fn pragma_once_includes_shared_library_once() {
+    let src = "#include <copy.bfpp>\n#include <compare.bfpp>\n#include <divmod.bfpp>\n\
+               @var a\n@var b\n@var t\n@at a +++ @copy(a, b, t) @at b .\n";
+    let out = preprocess("pragma_shared", src).unwrap();
+    assert_eq!(execute(&out), vec![3]);
+}
+
+#[test]
+fn pragma_once_inside_inactive_block_is_ignored() {
+    let dir = temp_path("pragma_conditional");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("part.bfpp"), "#ifdef ONCE\n#pragma once\n#endif\n+\n").unwrap();
+    let main_src = "#include \"part.bfpp\"\n#include \"part.bfpp\"\n.\n";
+    fs::write(dir.join("main.bfpp"), main_src).unwrap();
+    let main = dir.join("main.bfpp");
+    let main = main.to_str().unwrap();
+
+    let out = preprocess_with(main, &[], None).unwrap();
+    assert_eq!(execute(&out), vec![2]);
+    let out = preprocess_with(main, &["-D", "ONCE"], None).unwrap();
+    assert_eq!(execute(&out), vec![1]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pragma_once_breaks_include_cycles_and_reports_skips() {
+    let dir = temp_path("pragma_cycle");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.bfpp"), "#pragma once\n#include \"b.bfpp\"\n+\n").unwrap();
+    fs::write(dir.join("b.bfpp"), "#pragma once\n#include \"a.bfpp\"\n+\n").unwrap();
+    fs::write(dir.join("main.bfpp"), "#include \"a.bfpp\"\n#include \"b.bfpp\"\n.\n").unwrap();
+
+    let main = dir.join("main.bfpp");
+    let output = dir.join("main.bf");
+    let out = Command::new(bfpp_path())
+        .args([main.to_str().unwrap(), "-o", output.to_str().unwrap(), "-v"])
+        .output()
+        .expect("failed to run bfpp");
+    assert!(out.status.success());
+    assert_eq!(execute(&fs::read_to_string(&output).unwrap()), vec![2]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let expected = format!(
+        "note: skipping '{0}/a.bfpp' (#pragma once)\n  included from {0}/b.bfpp:2\n  \
+         included from {0}/a.bfpp:2\n  included from {0}/main.bfpp:1\n",
+        dir.display()
+    );
+    assert!(stderr.contains(&expected), "{}", stderr);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn unguarded_cycle_is_still_an_error() {
+    let dir = temp_path("plain_cycle");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.bfpp"), "#include \"b.bfpp\"\n").unwrap();
+    fs::write(dir.join("b.bfpp"), "#include \"a.bfpp\"\n").unwrap();
+    let err = preprocess_file(dir.join("a.bfpp").to_str().unwrap()).unwrap_err();
+    assert!(err.contains("include cycle"), "{}", err);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn command_line_defines_select_branches() {
+    let input = temp_path("defines.bfpp");
+    let src = "#ifdef DEBUG\n@repeat 68 + .\n#else\n@repeat 82 + .\n#endif\n\
+               #ifndef WIDTH\n#define WIDTH 3\n#endif\n[-] @repeat WIDTH + .\n";
+    fs::write(&input, src).unwrap();
+    let input = input.to_str().unwrap();
+
+    let out = preprocess_with(input, &[], None).unwrap();
+    assert_eq!(execute(&out), vec![b'R', 3]);
+    let out = preprocess_with(input, &["-D", "DEBUG", "-DWIDTH=5"], None).unwrap();
+    assert_eq!(execute(&out), vec![b'D', 5]);
+    for bad in [&["-D", "1+=x"][..], &["-D", ""], &["-D=3"], &["-DA-B"]] {
+        let err = preprocess_with(input, bad, None).unwrap_err();
+        assert!(err.starts_with("Usage: bfpp"), "{:?}: {}", bad, err);
+    }
+
+    let _ = fs::remove_file(input);
+}
+
+#[test]
+fn defines_cross_include_boundaries() {
+    let dir = temp_path("define_include");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("config.bfpp"),
+        "#ifndef CONFIG\n#define CONFIG\n#define STEP 2\n#ifdef FAST\n#undef STEP\n#define STEP 4\n#endif\n#endif\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("main.bfpp"),
+        "#include \"config.bfpp\"\n#include \"config.bfpp\"\n@repeat STEP + . @print \"STEP\"\n",
+    )
+    .unwrap();
+    let main = dir.join("main.bfpp");
+    let main = main.to_str().unwrap();
+
+    let out = preprocess_with(main, &[], None).unwrap();
+    assert_eq!(execute(&out), b"\x02STEP");
+    let out = preprocess_with(main, &["-D", "FAST"], None).unwrap();
+    assert_eq!(execute(&out), b"\x04STEP");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn defines_leave_directive_name_arguments_alone() {
+    let src = "#define count 3\n@var count\n@at count @repeat count + .\n";
+    let out = preprocess("define_name_arg", src).unwrap();
+    assert_eq!(execute(&out), vec![3]);
+
+    let input = temp_path("define_comment.bfpp");
+    fs::write(&input, "#define count 3\n@repeat count + . // count stays a word\n").unwrap();
+    let input = input.to_str().unwrap();
+    let out = preprocess_with(input, &["--keep-comments"], None).unwrap();
+    assert!(out.contains("count stays a word"), "{}", out);
+    assert_eq!(execute(&out), vec![3]);
+    let _ = fs::remove_file(input);
+}
+
+#[test]
+fn conditional_directive_errors() {
+    let err = preprocess("unterminated_ifdef", "+\n#ifdef X\n+\n").unwrap_err();
+    assert!(err.contains("unterminated #ifdef\n"), "{}", err);
+    let err = preprocess("stray_endif", "+\n#endif\n").unwrap_err();
+    assert!(err.contains("#endif without #ifdef\n"), "{}", err);
+    let err = preprocess("double_else", "#ifndef X\n#else\n#else\n#endif\n").unwrap_err();
+    assert!(err.contains("duplicate #else for #ifndef\n"), "{}", err);
+}
+
+#[test]
+fn repeat_counts_accept_expressions() {
+    let src = "@const WIDTH 3\n@const BASE ('A' - 1)\n\
+               @repeat BASE+WIDTH*2 + .\n[-] @repeat (WIDTH + 1)*(7 % 4) + .\n\
+               [-] @repeat '#' + . [-] @repeat 100/WIDTH-30 { + } .\n";
+    let out = preprocess("repeat_expr", src).unwrap();
+    assert_eq!(execute(&out), vec![b'F', 12, b'#', 3]);
+}
+
+#[test]
+fn repeat_expression_errors_point_at_source() {
+    let cases = [
+        ("repeat_unknown", "+\n+ @repeat 2*SIZE +\n", "2:13: repeat error: unknown constant 'SIZE'"),
+        ("repeat_div", "@const N 4\n@repeat N/(N-4) +\n", "2:10: repeat error: division by zero"),
+        ("repeat_negative", "@repeat 2-5 +\n", "1:9: repeat error: repeat count must be positive"),
+        ("const_twice", "@const N 1\n@const N 2\n", "2:8: repeat error: constant 'N' redefined"),
+        ("repeat_paren", "@repeat (1 + 2 .\n", "1:9: repeat error: missing ')'"),
+    ];
+    for (name, src, expected) in cases {
+        let err = preprocess(name, src).unwrap_err();
+        let path = temp_path(&format!("{}.bfpp", name));
+        assert!(err.contains(&format!("{}:{}", path.display(), expected)), "{}", err);
+    }
+}
+
+#[test]
+fn dependency_file_lists_include_graph() {
+    let dir = temp_path("deps");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/helper.bfpp"), "#pragma once\n+\n").unwrap();
+    fs::write(dir.join("sub/both.bfpp"), "#include \"helper.bfpp\"\n").unwrap();
+    fs::write(
+        dir.join("main.bfpp"),
+        "#include \"sub/both.bfpp\"\n#include \"sub/helper.bfpp\"\n#include <print.bfpp>\n.\n",
+    )
+    .unwrap();
+
+    let deps = dir.join("main.d");
+    let main = dir.join("main.bfpp");
+    let out = preprocess_with(main.to_str().unwrap(), &["-M", deps.to_str().unwrap()], None);
+    assert_eq!(execute(&out.unwrap()), vec![1]);
+    let text = fs::read_to_string(&deps).unwrap();
+    let d = dir.display();
+    let output = temp_path(&format!("{}.bf", main.to_str().unwrap().replace('/', "_")));
+    let expected = format!(
+        "{1}: \\\n  {0}/main.bfpp \\\n  {0}/sub/both.bfpp \\\n  {0}/sub/helper.bfpp\n\n\
+         {0}/sub/both.bfpp:\n\n{0}/sub/helper.bfpp:\n",
+        d,
+        output.display()
+    );
+    assert_eq!(text, expected);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn watch_rebuilds_when_an_include_changes() {
+    let dir = temp_path("watch");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("value.bfpp"), "@repeat 65 +\n").unwrap();
+    fs::write(dir.join("main.bfpp"), "#include \"value.bfpp\"\n.\n").unwrap();
+    let output = dir.join("main.bf");
+    let mut child = Command::new(bfpp_path())
+        .args([dir.join("main.bfpp").to_str().unwrap(), "-o", output.to_str().unwrap(), "--watch"])
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("failed to run bfpp");
+
+    let wait_for = |expected: &[u8]| {
+        for _ in 0..100 {
+            if let Ok(text) = fs::read_to_string(&output) {
+                if execute(&text) == expected {
+                    return true;
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        false
+    };
+    let first = wait_for(b"A");
+    fs::write(dir.join("value.bfpp"), "@repeat 66 +\n").unwrap();
+    let second = wait_for(b"B");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    assert!(first && second);
+}
+
+#[test]
+fn bf_runs_bfpp_sources_directly() {
+    let input = temp_path("direct.bfpp");
+    fs::write(&input, "#include <print.bfpp>\n@var c\n@print \"hi\\n\"\n").unwrap();
+    let bf = Command::new(bf_path()).arg(&input).output().expect("failed to run bf");
+    assert!(bf.status.success(), "{}", String::from_utf8_lossy(&bf.stderr));
+    assert_eq!(bf.stdout, b"hi\n");
+
+    let renamed = temp_path("direct.txt");
+    fs::write(&renamed, "@macro back() { < }\n+\n@back()\n").unwrap();
+    let bf = Command::new(bf_path())
+        .args([renamed.to_str().unwrap(), "--preprocess"])
+        .output()
+        .expect("failed to run bf");
+    let stderr = String::from_utf8_lossy(&bf.stderr);
+    let expected = format!("runtime error: pointer underflow at {}:1:17", renamed.display());
+    assert!(stderr.contains(&expected), "{}", stderr);
+
+    fs::write(&input, "+\n@oops()\n").unwrap();
+    let bf = Command::new(bf_path()).arg(&input).output().expect("failed to run bf");
+    assert!(!bf.status.success());
+    let stderr = String::from_utf8_lossy(&bf.stderr);
+    assert!(stderr.contains("macro error: unknown macro 'oops'"), "{}", stderr);
+
+    let _ = fs::remove_file(&input);
+    let _ = fs::remove_file(&renamed);
+}
+
+#[test]
+fn errors_point_into_included_files_with_snippet() {
+    let dir = temp_path("located");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lib.bfpp"), "@macro go() {\n\t@at  nowhere\n}\n").unwrap();
+    fs::write(dir.join("main.bfpp"), "#include \"lib.bfpp\"\n\n@go()\n").unwrap();
+    let err = preprocess_file(dir.join("main.bfpp").to_str().unwrap()).unwrap_err();
+    let expected = format!(
+        "{}/lib.bfpp:2:2: cell error: unknown cell 'nowhere'\n2 | \t@at  nowhere\n  | \t^\n",
+        dir.display()
+    );
+    assert!(err.contains(&expected), "{}", err);
+
+    fs::write(dir.join("main.bfpp"), "+\n  #include \"missing.bfpp\"\n").unwrap();
+    let err = preprocess_file(dir.join("main.bfpp").to_str().unwrap()).unwrap_err();
+    let expected = format!("{}/main.bfpp:2:3: failed to read", dir.display());
+    assert!(err.contains(&expected), "{}", err);
+    assert!(err.contains("2 |   #include \"missing.bfpp\"\n  |   ^"), "{}", err);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn keep_comments_and_pretty_preserve_commands() {
+    let input = temp_path("pretty.bfpp");
+    let src = "@var a\n@var b // b holds [the] count, +1 @at $x {}\n\
+               @at b @repeat 150 + # loop below.\n[ - @at a + @at b ]\n@at a .\n";
+    fs::write(&input, src).unwrap();
+    let input = input.to_str().unwrap();
+
+    let plain = preprocess_with(input, &[], None).unwrap();
+    let commented = preprocess_with(input, &["--keep-comments"], None).unwrap();
+    let pretty = preprocess_with(input, &["--keep-comments", "--pretty"], None).unwrap();
+    let commands = |text: &str| -> String {
+        text.chars().filter(|c| "+-<>[].,".contains(*c)).collect()
+    };
+    assert_eq!(commands(&commented), commands(&plain));
+    assert_eq!(commands(&pretty), commands(&plain));
+    assert!(commented.contains("// b holds ［the］ count， ＋1 ＠at ＄x ｛｝"), "{}", commented);
+    assert!(commented.contains("# loop below．"), "{}", commented);
+    assert_eq!(execute(&pretty), execute(&plain));
+
+    assert!(pretty.lines().all(|line| line.chars().count() <= 80), "{}", pretty);
+    assert!(pretty.contains("\n[\n    - < + >\n]\n"), "{}", pretty);
+    assert!(!pretty.lines().any(|line| line.ends_with(' ') || line.is_empty()), "{}", pretty);
+
+    let _ = fs::remove_file(input);
+}
+
+#[test]
+fn balanced_blocks_and_position_assertions() {
+    let src = "@var a\n@var b\n@var c\n@at b\n\
+               @balanced { @repeat 3 { > + < } [ - > + < ] @at c @at b }\n\
+               @assert_pos b\n@at c . @assert_pos c\n";
+    let out = preprocess("balanced_ok", src).unwrap();
+    assert_eq!(execute(&out), vec![3]);
+
+    let path = |name: &str| temp_path(&format!("{}.bfpp", name)).display().to_string();
+    let err = preprocess("balanced_drift", "@var a\n@balanced {\n  >> <\n}\n").unwrap_err();
+    let expected = format!(
+        "{}:4:1: cell error: @balanced block moves the pointer by +1",
+        path("balanced_drift")
+    );
+    assert!(err.contains(&expected), "{}", err);
+
+    let err = preprocess("balanced_loop", "+\n@balanced { [ > ] < }\n").unwrap_err();
+    assert!(
+        err.contains("loop opened on line 2 inside @balanced moves the pointer by +1"),
+        "{}",
+        err
+    );
+
+    let err = preprocess("assert_pos", "@var a\n@var b\n\n@at b >\n@assert_pos b\n").unwrap_err();
+    let expected = format!(
+        "{}:5:1: cell error: @assert_pos b failed: pointer is at cell 2, expected cell 1",
+        path("assert_pos")
+    );
+    assert!(err.contains(&expected), "{}", err);
+
+    let err = preprocess("assert_lost", "@var a\n+[>]\n@assert_pos a\n").unwrap_err();
+    assert!(err.contains("not statically known (unbalanced loop on line 2)"), "{}", err);
+}
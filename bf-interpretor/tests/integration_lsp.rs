@@ -0,0 +1,228 @@
+use bf::json::{self, Json};
+use std::env;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn lsp_path() -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("target");
+    path.push("debug");
+    path.push("bf-lsp");
+    if cfg!(windows) {
+        path.set_extension("exe");
+    }
+    path
+}
+
+fn doc_uri(name: &str) -> String {
+    format!("file://{}/programs/bfpp/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn open(uri: &str, text: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/didOpen")),
+        (
+            "params",
+            Json::object(vec![(
+                "textDocument",
+                Json::object(vec![("uri", Json::from(uri)), ("text", Json::from(text))]),
+            )]),
+        ),
+    ])
+}
+
+fn request(id: u64, method: &str, uri: &str, line: u64, character: u64) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("id", Json::from(id)),
+        ("method", Json::from(method)),
+        (
+            "params",
+            Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", Json::from(uri))])),
+                (
+                    "position",
+                    Json::object(vec![
+                        ("line", Json::from(line)),
+                        ("character", Json::from(character)),
+                    ]),
+                ),
+            ]),
+        ),
+    ])
+}
+
+fn session(messages: &[Json]) -> Vec<Json> {
+    let mut input = Vec::new();
+    for message in messages {
+        json::write_message(&mut input, message).unwrap();
+    }
+    raw_session(&input)
+}
+
+fn raw_session(input: &[u8]) -> Vec<Json> {
+    let mut child = Command::new(lsp_path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run bf-lsp");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input).unwrap();
+    stdin.flush().unwrap();
+    drop(stdin);
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    let mut reader = Cursor::new(out.stdout);
+    let mut replies = Vec::new();
+    while let Some(Ok(message)) = json::read_message(&mut reader).unwrap() {
+        replies.push(message);
+    }
+    replies
+}
+
+fn diagnostics(replies: &[Json]) -> Vec<&Json> {
+    replies
+        .iter()
+        .filter(|m| m.get("method").and_then(Json::as_str) == Some("textDocument/publishDiagnostics"))
+        .filter_map(|m| m.get("params")?.get("diagnostics")?.as_array())
+        .flatten()
+        .collect()
+}
+
+fn result(replies: &[Json], id: u64) -> &Json {
+    replies
+        .iter()
+        .find(|m| m.get("id").and_then(Json::as_u64) == Some(id))
+        .and_then(|m| m.get("result"))
+        .expect("missing response")
+}
+
+fn start_of(diagnostic: &Json) -> (u64, u64) {
+    let start = diagnostic.get("range").unwrap().get("start").unwrap();
+    (
+        start.get("line").and_then(Json::as_u64).unwrap(),
+        start.get("character").and_then(Json::as_u64).unwrap(),
+    )
+}
+
+#[test]
+fn reports_unmatched_bracket() {
+    let uri = "file:///tmp/unmatched.bf";
+    let replies = session(&[open(uri, "++\n+[->+<\n")]);
+    let diags = diagnostics(&replies);
+    assert_eq!(diags.len(), 1);
+    assert_eq!(start_of(diags[0]), (1, 1));
+    let message = diags[0].get("message").and_then(Json::as_str).unwrap();
+    assert!(message.contains("unmatched '['"), "{}", message);
+}
+
+#[test]
+fn hover_shows_loop_ir() {
+    let uri = "file:///tmp/hover.bf";
+    let replies = session(&[
+        open(uri, "++[->+<]\n"),
+        request(1, "textDocument/hover", uri, 0, 4),
+    ]);
+    let value = result(&replies, 1)
+        .get("contents")
+        .and_then(|c| c.get("value"))
+        .and_then(Json::as_str)
+        .unwrap();
+    assert!(value.contains("AddTo 1 1"), "{}", value);
+}
+
+#[test]
+fn reports_bfpp_errors_on_their_lines() {
+    let include_uri = doc_uri("include_error.bfpp");
+    let repeat_uri = doc_uri("repeat_error.bfpp");
+    let replies = session(&[
+        open(&include_uri, "+\n#include \"missing.bfpp\"\n"),
+        open(&repeat_uri, "+\n@repeat x +\n"),
+    ]);
+    let diags = diagnostics(&replies);
+    assert_eq!(diags.len(), 2);
+    assert_eq!(start_of(diags[0]).0, 1);
+    assert_eq!(start_of(diags[1]), (1, 8));
+}
+
+#[test]
+fn include_definition_and_folding() {
+    let uri = doc_uri("folding.bfpp");
+    let text = "#include \"../lib/memory.bfpp\"\n@repeat 2 {\n+\n}\n[\n-\n]\n";
+    let replies = session(&[
+        open(&uri, text),
+        request(1, "textDocument/definition", &uri, 0, 12),
+        request(2, "textDocument/foldingRange", &uri, 0, 0),
+    ]);
+    assert!(diagnostics(&replies).is_empty());
+    let target = result(&replies, 1).get("uri").and_then(Json::as_str).unwrap();
+    assert!(target.ends_with("programs/lib/memory.bfpp"), "{}", target);
+    let folds: Vec<(u64, u64)> = result(&replies, 2)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| {
+            (
+                f.get("startLine").and_then(Json::as_u64).unwrap(),
+                f.get("endLine").and_then(Json::as_u64).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(folds, vec![(1, 3), (4, 6)]);
+}
+
+#[test]
+fn folds_repeat_blocks_with_expression_counts() {
+    let uri = doc_uri("repeat_counts.bfpp");
+    let text = "@const WIDTH 2\n@repeat WIDTH*2 {\n+\n}\n@repeat 'A' {\n-\n}\n\
+                @repeat (WIDTH + 1) {\n>\n}\n";
+    let replies = session(&[open(&uri, text), request(1, "textDocument/foldingRange", &uri, 0, 0)]);
+    assert!(diagnostics(&replies).is_empty());
+    let folds: Vec<(u64, u64)> = result(&replies, 1)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| {
+            (
+                f.get("startLine").and_then(Json::as_u64).unwrap(),
+                f.get("endLine").and_then(Json::as_u64).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(folds, vec![(1, 3), (4, 6), (7, 9)]);
+}
+
+#[test]
+fn answers_malformed_messages_with_parse_errors() {
+    let uri = doc_uri("never_opened.bfpp");
+    let mut input = b"Content-Length: 5\r\n\r\n{oops".to_vec();
+    input.extend_from_slice(b"Content-Length: many\r\n\r\n");
+    json::write_message(&mut input, &request(1, "textDocument/foldingRange", &uri, 0, 0)).unwrap();
+    input.extend_from_slice(b"Content-Length: 999999999999\r\n\r\n{}");
+    let replies = raw_session(&input);
+    assert_eq!(replies.len(), 4);
+    assert_eq!(result(&replies, 1), &Json::Null);
+    for reply in [&replies[0], &replies[1], &replies[3]] {
+        assert_eq!(reply.get("id"), Some(&Json::Null));
+        let code = reply.get("error").and_then(|e| e.get("code"));
+        assert_eq!(code, Some(&Json::from(-32700i64)));
+    }
+}
+
+#[test]
+fn decodes_percent_escaped_uris() {
+    let dir = env::temp_dir().join(format!("bf lsp {}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("lib.bfpp"), "+\n").unwrap();
+    let dir = std::fs::canonicalize(&dir).unwrap();
+    let uri = format!("file://{}/main.bfpp", dir.to_str().unwrap().replace(' ', "%20"));
+    let replies = session(&[
+        open(&uri, "#include \"lib.bfpp\"\n"),
+        request(1, "textDocument/definition", &uri, 0, 12),
+    ]);
+    let target = result(&replies, 1).get("uri").and_then(Json::as_str).unwrap();
+    assert!(target.ends_with(&format!("%20{}/lib.bfpp", std::process::id())), "{}", target);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,4 +1,4 @@
-use bf::brackets::build_jumps;
+use bf::brackets::{build_jumps, match_brackets, BracketError};
 
 #[test]
 fn builds_nested_jumps() {
@@ -20,3 +20,18 @@ fn errors_on_unmatched_left() {
     let err = build_jumps(ops).unwrap_err();
     assert_eq!(err, "syntax error: unmatched '[' at 0");
 }
+
+#[test]
+fn reports_structured_error() {
+    let err = match_brackets(b"+[[]").unwrap_err();
+    assert_eq!(err, BracketError { index: 1, op: b'[' });
+}
+
+#[test]
+fn matches_procedure_parens_with_brackets() {
+    assert_eq!(build_jumps(b"([])").unwrap(), vec![3, 2, 1, 0]);
+    let err = match_brackets(b"([)]").unwrap_err();
+    assert_eq!(err, BracketError { index: 2, op: b')' });
+    let err = build_jumps(b"+(").unwrap_err();
+    assert_eq!(err, "syntax error: unmatched '(' at 1");
+}
@@ -0,0 +1,165 @@
+use bf::dap;
+use bf::json::{self, Json};
+use std::io::Cursor;
+
+fn frame(messages: &[&str]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (idx, body) in messages.iter().enumerate() {
+        let text = format!(
+            "{{\"seq\":{},\"type\":\"request\",{}}}",
+            idx + 1,
+            body
+        );
+        out.extend_from_slice(format!("Content-Length: {}\r\n\r\n", text.len()).as_bytes());
+        out.extend_from_slice(text.as_bytes());
+    }
+    out
+}
+
+fn exchange(messages: &[&str]) -> Vec<Json> {
+    let mut output = Vec::new();
+    dap::serve(Cursor::new(frame(messages)), &mut output).unwrap();
+    let mut reader = Cursor::new(output);
+    let mut replies = Vec::new();
+    while let Some(Ok(message)) = json::read_message(&mut reader).unwrap() {
+        replies.push(message);
+    }
+    replies
+}
+
+fn find<'a>(replies: &'a [Json], kind: &str, name: &str) -> Vec<&'a Json> {
+    let key = if kind == "event" { "event" } else { "command" };
+    replies
+        .iter()
+        .filter(|m| m.get("type").and_then(Json::as_str) == Some(kind))
+        .filter(|m| m.get(key).and_then(Json::as_str) == Some(name))
+        .collect()
+}
+
+fn stop_reasons(replies: &[Json]) -> Vec<&str> {
+    find(replies, "event", "stopped")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("reason")?.as_str())
+        .collect()
+}
+
+fn stdout_text(replies: &[Json]) -> String {
+    find(replies, "event", "output")
+        .iter()
+        .filter(|m| m.get("body").and_then(|b| b.get("category")).and_then(Json::as_str) == Some("stdout"))
+        .filter_map(|m| m.get("body")?.get("output")?.as_str())
+        .collect()
+}
+
+#[test]
+fn breakpoint_step_and_continue() {
+    let replies = exchange(&[
+        r#""command":"initialize","arguments":{"adapterID":"bf"}"#,
+        r#""command":"launch","arguments":{"program":"programs/tests/move_add.bf"}"#,
+        r#""command":"setBreakpoints","arguments":{"source":{"path":"programs/tests/move_add.bf"},"breakpoints":[{"line":3}]}"#,
+        r#""command":"configurationDone""#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"variables","arguments":{"variablesReference":1}"#,
+        r#""command":"next","arguments":{"threadId":1}"#,
+        r#""command":"next","arguments":{"threadId":1}"#,
+        r#""command":"continue","arguments":{"threadId":1}"#,
+        r#""command":"disconnect""#,
+    ]);
+
+    assert_eq!(find(&replies, "event", "initialized").len(), 1);
+    let bp = find(&replies, "response", "setBreakpoints")[0];
+    let first = &bp.get("body").unwrap().get("breakpoints").unwrap().as_array().unwrap()[0];
+    assert_eq!(first.get("verified").and_then(Json::as_bool), Some(true));
+    assert_eq!(first.get("line").and_then(Json::as_u64), Some(3));
+
+    assert_eq!(stop_reasons(&replies), vec!["breakpoint", "step", "step"]);
+
+    let trace = find(&replies, "response", "stackTrace")[0];
+    let frame = &trace.get("body").unwrap().get("stackFrames").unwrap().as_array().unwrap()[0];
+    assert_eq!(frame.get("line").and_then(Json::as_u64), Some(3));
+
+    let vars = find(&replies, "response", "variables")[0];
+    let vars = vars.get("body").unwrap().get("variables").unwrap().as_array().unwrap();
+    let cell1 = vars
+        .iter()
+        .find(|v| v.get("name").and_then(Json::as_str) == Some("[1]"))
+        .unwrap();
+    assert_eq!(cell1.get("value").and_then(Json::as_str), Some("65"));
+
+    assert_eq!(stdout_text(&replies), "A@");
+    let exited = find(&replies, "event", "exited")[0];
+    assert_eq!(
+        exited.get("body").and_then(|b| b.get("exitCode")).and_then(Json::as_u64),
+        Some(0)
+    );
+    assert_eq!(find(&replies, "event", "terminated").len(), 1);
+}
+
+#[test]
+fn next_steps_over_loop() {
+    let replies = exchange(&[
+        r#""command":"initialize""#,
+        r#""command":"launch","arguments":{"program":"programs/tests/step_over.bf","stopOnEntry":true}"#,
+        r#""command":"configurationDone""#,
+        r#""command":"next","arguments":{"threadId":1}"#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"next","arguments":{"threadId":1}"#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"disconnect""#,
+    ]);
+
+    assert_eq!(stop_reasons(&replies), vec!["entry", "step", "step"]);
+    let lines: Vec<u64> = find(&replies, "response", "stackTrace")
+        .iter()
+        .map(|m| {
+            let frames = m.get("body").unwrap().get("stackFrames").unwrap();
+            frames.as_array().unwrap()[0].get("line").and_then(Json::as_u64).unwrap()
+        })
+        .collect();
+    assert_eq!(lines, vec![2, 3]);
+    assert_eq!(stdout_text(&replies), "\x03\x06");
+}
+
+#[test]
+fn launch_reports_missing_program() {
+    let replies = exchange(&[
+        r#""command":"launch","arguments":{"program":"programs/tests/missing.bf"}"#,
+    ]);
+    let launch = find(&replies, "response", "launch")[0];
+    assert_eq!(launch.get("success").and_then(Json::as_bool), Some(false));
+}
+
+#[test]
+fn bfpp_breakpoints_use_source_lines() {
+    let replies = exchange(&[
+        r#""command":"initialize""#,
+        r#""command":"launch","arguments":{"program":"programs/tests/debug.bfpp"}"#,
+        r#""command":"setBreakpoints","arguments":{"source":{"path":"programs/tests/debug.bfpp"},"breakpoints":[{"line":5}]}"#,
+        r#""command":"configurationDone""#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"variables","arguments":{"variablesReference":1}"#,
+        r#""command":"continue","arguments":{"threadId":1}"#,
+        r#""command":"disconnect""#,
+    ]);
+
+    let bp = find(&replies, "response", "setBreakpoints")[0];
+    let first = &bp.get("body").unwrap().get("breakpoints").unwrap().as_array().unwrap()[0];
+    assert_eq!(first.get("verified").and_then(Json::as_bool), Some(true));
+    assert_eq!(first.get("line").and_then(Json::as_u64), Some(5));
+    assert_eq!(stop_reasons(&replies), vec!["breakpoint"]);
+
+    let trace = find(&replies, "response", "stackTrace")[0];
+    let frame = &trace.get("body").unwrap().get("stackFrames").unwrap().as_array().unwrap()[0];
+    assert_eq!(frame.get("line").and_then(Json::as_u64), Some(5));
+    let source = frame.get("source").unwrap();
+    assert_eq!(source.get("name").and_then(Json::as_str), Some("debug.bfpp"));
+
+    let vars = find(&replies, "response", "variables")[0];
+    let vars = vars.get("body").unwrap().get("variables").unwrap().as_array().unwrap();
+    let cell0 = vars
+        .iter()
+        .find(|v| v.get("name").and_then(Json::as_str) == Some("[0]"))
+        .unwrap();
+    assert_eq!(cell0.get("value").and_then(Json::as_str), Some("65"));
+    assert_eq!(stdout_text(&replies), "A");
+}
@@ -0,0 +1,63 @@
+use bf::dialect::{translate, Dialect};
+
+#[test]
+fn ook_tokens_may_span_whitespace() {
+    let ook = Dialect::builtin("ook").unwrap();
+    let (ops, offsets) = ook.tokenize(b"Ook. Ook.\nOok! Ook?  note Ook?\n  Ook!");
+    assert_eq!(ops, b"+[]");
+    assert_eq!(offsets, vec![0, 10, 26]);
+}
+
+#[test]
+fn translations_round_trip() {
+    let source = b"++[>+<-]>.,";
+    for name in Dialect::names() {
+        let dialect = Dialect::builtin(name).unwrap();
+        let text = translate(source, &Dialect::brainfuck(), &dialect).unwrap();
+        assert_eq!(dialect.tokenize(text.as_bytes()).0, source, "{}", name);
+    }
+    let reversed = Dialect::builtin("reversefuck").unwrap();
+    assert_eq!(
+        translate(source, &Dialect::brainfuck(), &reversed).unwrap(),
+        "--]<->+[<,.\n"
+    );
+}
+
+#[test]
+fn token_tables_define_custom_dialects() {
+    let table = "# moo\n> moo right\n< moo left\n+ MOO\n- moo\n. say\n, hear\n[ (\n] )\n";
+    let cow = Dialect::parse_table(table).unwrap();
+    let (ops, _) = cow.tokenize(b"MOO MOO ( moo   right MOO moo left moo ) moo right say");
+    assert_eq!(ops, b"++[>+<-]>.");
+    assert_eq!(cow.render(b"+[-]"), "MOO ( moo )\n");
+
+    let err = Dialect::parse_table("> a\n< b\n").unwrap_err();
+    assert_eq!(err, "dialect table error: no token for '+-.,[]'");
+    let err = Dialect::parse_table("> a\n< a\n").unwrap_err();
+    assert_eq!(err, "dialect table error: token used for two commands at line 2");
+    let err = Dialect::parse_table("x a\n").unwrap_err();
+    assert_eq!(err, "dialect table error: expected one of the eight BF commands at line 1");
+}
+
+#[test]
+fn pbrain_extends_brainfuck() {
+    let pbrain = Dialect::builtin("pbrain").unwrap();
+    assert!(!pbrain.is_brainfuck());
+    assert_eq!(pbrain.tokenize(b"+(a-):").0, b"+(-):");
+    assert_eq!(Dialect::brainfuck().tokenize(b"+(a-):").0, b"+-");
+    let ook = Dialect::builtin("ook").unwrap();
+    let err = translate(b"+(-):", &pbrain, &ook).unwrap_err();
+    assert_eq!(err, "target dialect has no token for '('");
+}
+
+#[test]
+fn extensions_combine_with_plus() {
+    let combined = Dialect::resolve("2d+framebuffer").unwrap();
+    assert_eq!(combined.tokenize(b"+v#^x").0, b"+v#^");
+    assert!(combined.supports(b'#') && combined.supports(b'v'));
+    assert_eq!(Dialect::builtin("Framebuffer+2D"), Some(combined));
+    assert_eq!(Dialect::builtin("2d+extended1"), None);
+    assert_eq!(Dialect::builtin("2d+ook"), None);
+    let err = Dialect::resolve("2d+").unwrap_err();
+    assert!(err.contains("unknown dialect '2d+'"), "{}", err);
+}
@@ -0,0 +1,39 @@
+use bf::format::{format, minify};
+use bf::parse::filter_ops;
+
+#[test]
+fn indents_loops_and_groups_runs() {
+    let src = b"++ +[>+ +<-]";
+    let out = format(src).unwrap();
+    assert_eq!(out, "+++\n[\n    > ++ < -\n]\n");
+}
+
+#[test]
+fn keeps_comments_and_is_idempotent() {
+    let src = b"init cell\n+++[ loop\n->+<]done\n";
+    let once = format(src).unwrap();
+    assert_eq!(once, "init cell\n+++\n[ loop\n    - > + <\n] done\n");
+    assert_eq!(format(once.as_bytes()).unwrap(), once);
+    assert_eq!(filter_ops(once.as_bytes()), filter_ops(src));
+}
+
+#[test]
+fn format_rejects_unmatched_brackets() {
+    let err = format(b"+[").unwrap_err();
+    assert_eq!(err, "syntax error: unmatched '[' at 1");
+}
+
+#[test]
+fn minify_cancels_inverse_ops() {
+    assert_eq!(minify(b"+ +-- x > <>.").unwrap(), ">.");
+}
+
+#[test]
+fn minify_keeps_left_then_right_moves() {
+    assert_eq!(minify(b"<>+.").unwrap(), "<>+.");
+}
+
+#[test]
+fn minify_drops_dead_loops() {
+    assert_eq!(minify(b"[comment.]+[-][>+<]+-[.]>.").unwrap(), "+[-]>.");
+}
@@ -0,0 +1,36 @@
+use bf::framebuffer::Framebuffer;
+use bf::image::Format;
+use std::env;
+
+#[test]
+fn parses_region_specs() {
+    let fb = Framebuffer::parse("32x24x3@1000").unwrap();
+    assert_eq!((fb.width, fb.height, fb.bytes_per_pixel, fb.offset), (32, 24, 3, 1000));
+    assert_eq!(fb.len(), 32 * 24 * 3);
+    assert_eq!(fb.format(), Format::Ppm);
+    assert_eq!(Framebuffer::parse("8x8x1").unwrap().format(), Format::Pgm);
+
+    let err = Framebuffer::parse("8x8").unwrap_err();
+    assert_eq!(err, "invalid framebuffer '8x8': expected WxHxBPP[@OFFSET]");
+    let err = Framebuffer::parse("0x8x1").unwrap_err();
+    assert_eq!(err, "framebuffer width and height must be greater than 0");
+    let err = Framebuffer::parse("9999999999x9999999999x3").unwrap_err();
+    assert_eq!(err, "framebuffer region overflows the tape");
+    let err = Framebuffer::parse(&format!("1x1x1@{}", usize::MAX)).unwrap_err();
+    assert_eq!(err, "framebuffer region overflows the tape");
+}
+
+#[test]
+fn captures_numbered_frames_of_the_region() {
+    let mut fb = Framebuffer::parse("2x1x3@1").unwrap();
+    fb.dir = env::temp_dir().join(format!("bf-frames-unit-{}", std::process::id()));
+    let first = fb.capture(&[9, 1, 2, 3, 4, 5, 6, 9]).unwrap();
+    assert_eq!(first, fb.dir.join("frame-00000.ppm"));
+    assert_eq!(std::fs::read(&first).unwrap(), b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+
+    let second = fb.capture(&[0, 7]).unwrap();
+    assert_eq!(second, fb.dir.join("frame-00001.ppm"));
+    assert_eq!(std::fs::read(&second).unwrap(), b"P6\n2 1\n255\n\x07\x00\x00\x00\x00\x00");
+    assert_eq!(fb.frames(), 2);
+    let _ = std::fs::remove_dir_all(&fb.dir);
+}
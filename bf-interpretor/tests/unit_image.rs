@@ -0,0 +1,31 @@
+use bf::image::{encode_pbm, encode_pgm, encode_ppm, Format};
+
+#[test]
+fn encodes_pgm_with_padding() {
+    let image = encode_pgm(3, 2, &[0, 128, 255, 7]);
+    assert_eq!(image, b"P5\n3 2\n255\n\x00\x80\xff\x07\x00\x00");
+}
+
+#[test]
+fn packs_pbm_rows_to_bytes() {
+    let mut cells = vec![0; 20];
+    cells[0] = 1;
+    cells[9] = 200;
+    cells[10] = 1;
+    let image = encode_pbm(10, 2, &cells);
+    assert_eq!(image, b"P4\n10 2\n\x80\x40\x80\x00");
+}
+
+#[test]
+fn picks_format_from_extension() {
+    assert_eq!(Format::from_path("out/grid.PGM"), Ok(Format::Pgm));
+    assert_eq!(Format::from_path("grid.pbm"), Ok(Format::Pbm));
+    let err = Format::from_path("grid.png").unwrap_err();
+    assert_eq!(err, "unsupported image format 'grid.png' (expected .pbm, .pgm or .ppm)");
+}
+
+#[test]
+fn encodes_ppm_triplets() {
+    let image = encode_ppm(1, 2, &[1, 2, 3, 4]);
+    assert_eq!(image, b"P6\n1 2\n255\n\x01\x02\x03\x04\x00\x00");
+}
@@ -1,4 +1,5 @@
-use bf::{brackets, ir, parse};
+use bf::vm::Topology;
+use bf::{brackets, compile_ops_for, ir, parse};
 
 #[test]
 fn builds_ir_for_loop() {
@@ -18,3 +19,45 @@ fn errors_on_length_mismatch() {
     let err = ir::build(&ops, &jumps).unwrap_err();
     assert_eq!(err, "ops and jumps length mismatch");
 }
+
+#[test]
+fn emits_canonical_bf() {
+    let ir = vec![
+        ir::Instr::Add(-2),
+        ir::Instr::AddTo(2, -1),
+        ir::Instr::AddMul(vec![(-1, 3), (1, 1)]),
+        ir::Instr::Scan(-1),
+        ir::Instr::SetZero,
+        ir::Instr::Jz(7),
+        ir::Instr::Output,
+        ir::Instr::Jnz(5),
+    ];
+    assert_eq!(
+        ir::emit_bf(&ir).unwrap(),
+        "--[->>-<<][<+++>>+<-][<][-][.]\n"
+    );
+}
+
+#[test]
+fn lowers_vertical_moves_for_grids() {
+    let ops = b"^v>";
+    let jumps = brackets::build_jumps(ops).unwrap();
+    let ir = ir::build_for(ops, &jumps, &Topology::Grid { width: 8 }).unwrap();
+    assert_eq!(ir, vec![ir::Instr::Vertical(-1), ir::Instr::Vertical(1), ir::Instr::Move(1)]);
+    let ir = ir::build(ops, &jumps).unwrap();
+    assert_eq!(ir, vec![ir::Instr::Xor, ir::Instr::Move(1)]);
+}
+
+#[test]
+fn emits_grid_scans_as_vertical_moves() {
+    let grid = Topology::Grid { width: 4 };
+    let ops = b"+[v]^^[^]>>>>[>]";
+    let program = compile_ops_for(ops, (0..ops.len()).collect(), false, &grid).unwrap();
+    assert!(program.ir.contains(&ir::Instr::ScanVertical(1)));
+    assert!(program.ir.contains(&ir::Instr::Vertical(-2)));
+    let emitted = ir::emit_bf(&program.ir).unwrap();
+    assert_eq!(emitted, "+[v]^^[^]>>>>[>]\n");
+    let ops = emitted.trim_end().as_bytes();
+    let reparsed = compile_ops_for(ops, (0..ops.len()).collect(), false, &grid);
+    assert_eq!(reparsed.unwrap().ir, program.ir);
+}
@@ -1,5 +1,6 @@
 use bf::ir::Instr;
 use bf::opt;
+use bf::vm::Topology;
 
 #[test]
 fn merges_add_and_move() {
@@ -27,3 +28,109 @@ fn detects_setzero() {
     opt::loop_analysis(&mut ir);
     assert_eq!(ir, vec![Instr::SetZero]);
 }
+
+#[test]
+fn optimize_keeps_positions_aligned() {
+    let mut ir = vec![
+        Instr::Add(1),
+        Instr::Add(1),
+        Instr::Jz(4),
+        Instr::Add(-1),
+        Instr::Jnz(2),
+        Instr::Output,
+    ];
+    let mut positions = vec![0, 1, 3, 4, 5, 7];
+    opt::optimize(&mut ir, &mut positions).unwrap();
+    assert_eq!(ir, vec![Instr::Add(2), Instr::SetZero, Instr::Output]);
+    assert_eq!(positions, vec![0, 3, 7]);
+}
+
+#[test]
+fn empty_addmul_becomes_setzero() {
+    let mut ir = vec![
+        Instr::Jz(4),
+        Instr::Add(-1),
+        Instr::Move(1),
+        Instr::Move(-1),
+        Instr::Jnz(0),
+    ];
+    opt::loop_analysis(&mut ir);
+    assert_eq!(ir, vec![Instr::SetZero]);
+}
+
+#[test]
+fn keeps_procedure_boundaries() {
+    let mut ir = vec![
+        Instr::Add(1),
+        Instr::Define(3),
+        Instr::Add(1),
+        Instr::Return,
+        Instr::Add(1),
+        Instr::Call,
+    ];
+    let mut positions = (0..ir.len()).collect();
+    opt::optimize(&mut ir, &mut positions).unwrap();
+    assert_eq!(ir[1], Instr::Define(3));
+    assert_eq!(ir.len(), 6);
+
+    let mut ir = vec![Instr::Jz(2), Instr::Define(3), Instr::Jnz(0), Instr::Return];
+    let mut positions = (0..ir.len()).collect();
+    let err = opt::optimize(&mut ir, &mut positions).unwrap_err();
+    assert_eq!(err, "unmatched ']' at 2");
+}
+
+#[test]
+fn extended_ops_are_loop_barriers() {
+    let mut ir = vec![Instr::Jz(3), Instr::Add(-1), Instr::Shl, Instr::Jnz(0)];
+    opt::loop_analysis(&mut ir);
+    assert_eq!(ir, vec![Instr::Jz(3), Instr::Add(-1), Instr::Shl, Instr::Jnz(0)]);
+
+    let mut ir = vec![Instr::Add(1), Instr::Store, Instr::Add(1)];
+    opt::merge_ops(&mut ir);
+    assert_eq!(ir, vec![Instr::Add(1), Instr::Store, Instr::Add(1)]);
+}
+
+#[test]
+fn detects_vertical_scans_on_grids() {
+    let grid = Topology::Grid { width: 16 };
+    let mut ir = vec![Instr::Jz(2), Instr::Vertical(-1), Instr::Jnz(0)];
+    let mut positions = (0..ir.len()).collect();
+    opt::optimize_for(&mut ir, &mut positions, &grid).unwrap();
+    assert_eq!(ir, vec![Instr::ScanVertical(-1)]);
+
+    let mut ir = vec![Instr::Jz(2), Instr::Move(-16), Instr::Jnz(0)];
+    let mut positions = (0..ir.len()).collect();
+    opt::optimize_for(&mut ir, &mut positions, &grid).unwrap();
+    assert_eq!(ir, vec![Instr::Jz(2), Instr::Move(-16), Instr::Jnz(0)]);
+}
+
+#[test]
+fn keeps_loops_whose_offsets_wrap_onto_each_other() {
+    let grid = Topology::Grid { width: 4 };
+    let body = [Instr::Add(-1), Instr::Move(4), Instr::Add(1), Instr::Move(-4)];
+    let mut ir = vec![Instr::Jz(5)];
+    ir.extend(body.iter().cloned());
+    ir.push(Instr::Jnz(0));
+    let expected = ir.clone();
+    let mut positions = (0..ir.len()).collect();
+    opt::optimize_for(&mut ir, &mut positions, &grid).unwrap();
+    assert_eq!(ir, expected);
+
+    let mut ir = vec![
+        Instr::Jz(7),
+        Instr::Add(-1),
+        Instr::Move(1),
+        Instr::Add(1),
+        Instr::Move(-4),
+        Instr::Add(2),
+        Instr::Move(3),
+        Instr::Jnz(0),
+    ];
+    let expected = ir.clone();
+    let mut positions = (0..ir.len()).collect();
+    opt::optimize_for(&mut ir, &mut positions, &grid).unwrap();
+    assert_eq!(ir, expected);
+    let mut positions = (0..ir.len()).collect();
+    opt::optimize_for(&mut ir, &mut positions, &Topology::Linear).unwrap();
+    assert_eq!(ir, vec![Instr::AddMul(vec![(-3, 2), (1, 1)])]);
+}
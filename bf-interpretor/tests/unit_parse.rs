@@ -1,4 +1,4 @@
-use bf::parse::filter_ops;
+use bf::parse::{filter_ops, filter_ops_with, line_col, op_offsets, op_offsets_with};
 
 #[test]
 fn filters_only_ops() {
@@ -6,3 +6,18 @@ fn filters_only_ops() {
     let output = filter_ops(input);
     assert_eq!(output, b"+<>-.,[]");
 }
+
+#[test]
+fn maps_ops_to_source_lines() {
+    let input = b"a+\n >.";
+    assert_eq!(op_offsets(input), vec![1, 4, 5]);
+    assert_eq!(line_col(input, 4), (2, 2));
+}
+
+#[test]
+fn keeps_extension_ops_when_asked() {
+    let input = b"+a$b@";
+    assert_eq!(filter_ops_with(input, b"$@"), b"+$@");
+    assert_eq!(op_offsets_with(input, b"$"), vec![0, 2]);
+    assert_eq!(filter_ops(input), b"+");
+}
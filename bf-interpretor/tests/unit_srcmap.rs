@@ -0,0 +1,38 @@
+use bf::srcmap::{Location, SourceMap};
+
+fn loc(file: &str, line: usize, col: usize) -> Option<Location> {
+    Some(Location {
+        file: file.to_string(),
+        line,
+        col,
+    })
+}
+
+#[test]
+fn copied_and_generated_runs_compress() {
+    let origins = [(0, 1, 1), (0, 1, 2), (0, 1, 3), (1, 4, 7), (1, 4, 7), (1, 4, 7), (0, 2, 1)];
+    let map = SourceMap::build(vec!["a.bfpp".to_string(), "b.bfpp".to_string()], &origins);
+    assert_eq!(map.to_string().lines().count(), 1 + 2 + 3);
+    assert_eq!(map.lookup(2), loc("a.bfpp", 1, 3));
+    assert_eq!(map.lookup(5), loc("b.bfpp", 4, 7));
+    assert_eq!(map.lookup(6), loc("a.bfpp", 2, 1));
+    assert_eq!(map.lookup(100), loc("a.bfpp", 2, 1));
+}
+
+#[test]
+fn serialized_map_parses_back() {
+    let origins = [(0, 3, 5), (0, 3, 6), (0, 3, 6), (0, 9, 2)];
+    let map = SourceMap::build(vec!["dir with space/x.bfpp".to_string()], &origins);
+    let parsed = SourceMap::parse(&map.to_string()).unwrap();
+    assert_eq!(parsed, map);
+    for (offset, &(_, line, col)) in origins.iter().enumerate() {
+        assert_eq!(parsed.lookup(offset), loc("dir with space/x.bfpp", line, col));
+    }
+}
+
+#[test]
+fn malformed_map_is_rejected() {
+    assert!(SourceMap::parse("not a map\n").is_err());
+    let err = SourceMap::parse("bfpp-source-map 1\nfile 0 a\n0 3 1 1 c\n").unwrap_err();
+    assert_eq!(err, "source map error: invalid entry at line 3");
+}
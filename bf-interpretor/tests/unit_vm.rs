@@ -1,7 +1,7 @@
 use bf::io;
 use bf::io::Debug;
 use bf::ir::Instr;
-use bf::vm::Vm;
+use bf::vm::{Halt, Schedule, Topology, Vm, Watch, WatchKind};
 use std::io::sink;
 use std::io::Cursor;
 
@@ -69,3 +69,293 @@ fn grows_tape_on_move() {
     assert_eq!(machine.pointer(), 10);
     assert!(machine.tape().len() > 10);
 }
+
+#[test]
+fn watch_triggers_on_addmul_target() {
+    let ir = vec![
+        Instr::Add(3),
+        Instr::AddMul(vec![(2, 2)]),
+        Instr::Move(2),
+        Instr::Output,
+    ];
+    let mut machine = Vm::with_capacity(4).unwrap();
+    machine.add_watch(Watch {
+        cell: 2,
+        kind: WatchKind::Write,
+    });
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    let hit = match halt {
+        Halt::Watch(hit) => hit,
+        other => panic!("expected watch hit, got {:?}", other),
+    };
+    assert_eq!((hit.ip, hit.step, hit.old, hit.new), (1, 1, 0, 6));
+    assert_eq!(machine.ip(), 2);
+
+    let halt = machine
+        .resume::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(halt, Halt::Finished);
+    assert_eq!(output.into_inner().unwrap(), vec![6]);
+}
+
+#[test]
+fn run_ir_restarts_a_finished_program() {
+    let ir = vec![Instr::Add(1), Instr::Output, Instr::Store, Instr::Move(1), Instr::Add(1)];
+    let mut machine = Vm::with_capacity(1).unwrap();
+    machine.set_initial_tape(&[4]).unwrap();
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    for _ in 0..2 {
+        let halt = machine
+            .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+            .unwrap();
+        assert_eq!(halt, Halt::Finished);
+        assert_eq!(machine.steps(), 5);
+        assert_eq!(machine.pointer(), 1);
+        assert_eq!(&machine.tape()[..2], &[5, 1]);
+        assert_eq!(machine.storage(), 5);
+    }
+    let halt = machine
+        .resume::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(halt, Halt::Finished);
+    assert_eq!(output.into_inner().unwrap(), vec![5, 5]);
+}
+
+#[test]
+fn watch_write_ignores_clearing_a_zero_cell() {
+    let ir = vec![
+        Instr::AddMul(vec![]),
+        Instr::SetZero,
+        Instr::AddMul(vec![(1, 1)]),
+        Instr::Add(1),
+    ];
+    let mut machine = Vm::with_capacity(2).unwrap();
+    machine.add_watch(Watch {
+        cell: 0,
+        kind: WatchKind::Write,
+    });
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert!(matches!(halt, Halt::Watch(ref hit) if hit.ip == 3 && hit.old == 0 && hit.new == 1));
+}
+
+#[test]
+fn watch_change_ignores_same_value_writes() {
+    let ir = vec![Instr::SetZero, Instr::Add(2), Instr::AddTo(1, 1)];
+    let mut machine = Vm::with_capacity(2).unwrap();
+    machine.add_watch(Watch {
+        cell: 0,
+        kind: WatchKind::Change,
+    });
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert!(matches!(halt, Halt::Watch(ref hit) if hit.ip == 1));
+    let halt = machine
+        .resume::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert!(matches!(halt, Halt::Watch(ref hit) if hit.ip == 2 && hit.new == 0));
+}
+
+#[test]
+fn watch_value_triggers_when_the_cell_becomes_the_value() {
+    let ir = vec![Instr::Add(2), Instr::Add(0), Instr::Add(-2), Instr::Add(2)];
+    let mut machine = Vm::with_capacity(1).unwrap();
+    machine.add_watch(Watch {
+        cell: 0,
+        kind: WatchKind::Equals(2),
+    });
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert!(matches!(halt, Halt::Watch(ref hit) if hit.ip == 0 && hit.old == 0));
+    let halt = machine
+        .resume::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert!(matches!(halt, Halt::Watch(ref hit) if hit.ip == 3 && hit.old == 0));
+}
+
+#[test]
+fn watch_value_range_triggers_on_entry() {
+    let ir = vec![
+        Instr::Add(5),
+        Instr::Jz(3),
+        Instr::Add(-1),
+        Instr::Jnz(1),
+    ];
+    let mut machine = Vm::with_capacity(1).unwrap();
+    machine.add_watch(Watch {
+        cell: 0,
+        kind: WatchKind::Range(1, 2),
+    });
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert!(matches!(halt, Halt::Watch(ref hit) if hit.old == 3 && hit.new == 2));
+    assert_eq!(machine.tape()[0], 2);
+}
+
+#[test]
+fn stops_at_breakpoints_and_steps() {
+    let ir = vec![Instr::Add(1), Instr::Move(1), Instr::Add(2), Instr::Output];
+    let mut machine = Vm::with_capacity(2).unwrap();
+    machine.set_breakpoints([2]);
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(halt, Halt::Breakpoint(2));
+    let halt = machine
+        .step::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(halt, Halt::Stepped);
+    assert_eq!((machine.ip(), machine.tape()[1]), (3, 2));
+    let halt = machine
+        .resume::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(halt, Halt::Finished);
+}
+
+#[test]
+fn calls_procedures_by_cell_value() {
+    let ir = vec![
+        Instr::Add(2),
+        Instr::Define(4),
+        Instr::Add(1),
+        Instr::Output,
+        Instr::Return,
+        Instr::Add(-1),
+        Instr::Define(8),
+        Instr::Call,
+        Instr::Return,
+        Instr::Add(1),
+        Instr::Call,
+        Instr::Output,
+    ];
+    let mut machine = Vm::with_capacity(1).unwrap();
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(output.into_inner().unwrap(), vec![3, 3]);
+    assert_eq!(machine.call_depth(), 0);
+
+    let mut machine = Vm::with_capacity(1).unwrap();
+    let mut output = io::Output::new(Vec::new());
+    let err = machine
+        .run_ir::<_, _, Vec<u8>>(&[Instr::Call], &mut input, &mut output, None, None)
+        .unwrap_err();
+    assert_eq!(err, "runtime error: undefined procedure 0");
+}
+
+fn run_threads(ir: &[Instr], schedule: Schedule) -> Vec<u8> {
+    let mut machine = Vm::with_capacity(4).unwrap();
+    machine.set_schedule(schedule).unwrap();
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(halt, Halt::Finished);
+    assert_eq!(machine.thread_count(), 1);
+    output.into_inner().unwrap()
+}
+
+#[test]
+fn forked_threads_share_the_tape_and_interleave() {
+    let ir = vec![Instr::Fork, Instr::Add(2), Instr::Output, Instr::Output];
+    assert_eq!(run_threads(&ir, Schedule::RoundRobin { quantum: 1 }), vec![3, 2, 3, 2]);
+    assert_eq!(run_threads(&ir, Schedule::RoundRobin { quantum: 3 }), vec![2, 3, 3, 2]);
+    let mut late = vec![Instr::Move(0); 5];
+    late.extend(ir.iter().cloned());
+    assert_eq!(run_threads(&late, Schedule::RoundRobin { quantum: 3 }), vec![2, 3, 3, 2]);
+
+    let random = Schedule::Random { quantum: 2, seed: 7 };
+    let first = run_threads(&ir, random);
+    assert_eq!(first, run_threads(&ir, random));
+    let mut sorted = first.clone();
+    sorted.sort();
+    assert_eq!(sorted, vec![2, 2, 3, 3]);
+
+    let mut machine = Vm::with_capacity(1).unwrap();
+    let err = machine.set_schedule(Schedule::RoundRobin { quantum: 0 }).unwrap_err();
+    assert_eq!(err, "scheduler quantum must be greater than 0");
+}
+
+#[test]
+fn extended_type_one_ops_use_the_storage_register() {
+    let ir = vec![
+        Instr::Add(12),
+        Instr::Store,
+        Instr::Shr,
+        Instr::Xor,
+        Instr::Output,
+        Instr::Not,
+        Instr::And,
+        Instr::Output,
+        Instr::Shl,
+        Instr::Or,
+        Instr::Output,
+        Instr::Load,
+        Instr::End,
+        Instr::Output,
+    ];
+    let mut machine = Vm::with_capacity(1).unwrap();
+    let mut input = io::Input::new(Cursor::new(Vec::new()));
+    let mut output = io::Output::new(Vec::new());
+    let halt = machine
+        .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+        .unwrap();
+    assert_eq!(halt, Halt::Finished);
+    assert_eq!(output.into_inner().unwrap(), vec![10, 4, 12]);
+    assert_eq!(machine.storage(), 12);
+    assert_eq!(machine.tape()[0], 12);
+}
+
+#[test]
+fn wraps_moves_around_the_grid() {
+    let run = |ir: Vec<Instr>| {
+        let mut machine = Vm::with_capacity(10).unwrap();
+        machine.set_topology(Topology::grid(4).unwrap());
+        let mut input = io::Input::new(Cursor::new(Vec::new()));
+        let mut output = io::Output::new(Vec::new());
+        machine
+            .run_ir::<_, _, Vec<u8>>(&ir, &mut input, &mut output, None, None)
+            .unwrap();
+        machine
+    };
+    let machine = run(vec![Instr::Move(4), Instr::Add(1)]);
+    assert_eq!(machine.topology().locate(machine.pointer()), (0, 0));
+    assert_eq!(machine.tape()[0], 1);
+    let machine = run(vec![Instr::Move(-1), Instr::Vertical(-1), Instr::Add(1)]);
+    assert_eq!(machine.topology().locate(machine.pointer()), (3, 2));
+    assert_eq!(machine.tape().len(), 12);
+    let machine = run(vec![
+        Instr::Add(1),
+        Instr::Vertical(2),
+        Instr::Add(1),
+        Instr::Vertical(-2),
+        Instr::ScanVertical(-1),
+        Instr::Add(1),
+        Instr::Scan(-1),
+    ]);
+    assert_eq!(machine.topology().locate(machine.pointer()), (3, 1));
+    assert_eq!(machine.grid_rows(), 3);
+    assert!(Topology::grid(0).is_err());
+}